@@ -6,6 +6,8 @@ enum LightMode {
 	HSI = 1,
 }
 
+## Color temperature range of the preview gradient, and the default range of lights.
+## The range of each light is sent by the server in "describe".
 const CT_MIN := 2700
 const CT_MAX := 7500
 
@@ -81,6 +81,16 @@ func _tab_changed(tab: int) -> void:
 	state_changed.emit(state)
 
 
+## Limit the color temperature control to the range supported by the lights.
+func set_ct_range(ct_min: int, ct_max: int) -> void:
+	process_signals = false
+	ct_control.set_range(ct_min, ct_max)
+	state.ct = clampi(state.ct, ct_min, ct_max)
+	ct_control.value = state.ct
+	_update_preview()
+	process_signals = true
+
+
 func set_state(new_state: LCTypes.LightState) -> void:
 	process_signals = false # inhibit change signals when setting from outside
 	state = new_state.copy()
@@ -17,6 +17,16 @@ var value: int:
 		return spinbox.value
 
 
+## Change the range of the slider, clamping the value to it.
+func set_range(new_min: float, new_max: float) -> void:
+	min_value = new_min
+	max_value = new_max
+	slider.min_value = min_value
+	slider.max_value = max_value
+	spinbox.min_value = min_value
+	spinbox.max_value = max_value
+
+
 func _drag_ended(_value_changed: bool) -> void:
 	spinbox.value = slider.value
 
@@ -7,6 +7,7 @@ var num_lights: int = 0
 var grid_template_row: Array[Node]
 var light_rows: Array
 var light_state: Array
+var lights_info: Array
 
 
 ## Handler when light control state changed.
@@ -27,6 +28,25 @@ func _swatch_input(event, idx) -> void:
 			light_rows[row][0].button_pressed = false
 		light_rows[idx][0].button_pressed = true			
 		lc.set_state(light_state[idx])
+		_update_ct_range()
+
+
+## Limit the color temperature control to the range that all selected lights support,
+## as the server rejects values outside a light's range.
+func _update_ct_range() -> void:
+	var ct_min := 0
+	var ct_max := 100000
+	var any_selected := false
+	for row in len(light_rows):
+		if light_rows[row][0].button_pressed and row < len(lights_info):
+			var ct_range: Array = lights_info[row]["ct_range"]
+			ct_min = maxi(ct_min, int(ct_range[0]))
+			ct_max = mini(ct_max, int(ct_range[1]))
+			any_selected = true
+	if not any_selected or ct_min >= ct_max:
+		ct_min = LCTypes.CT_MIN
+		ct_max = LCTypes.CT_MAX
+	lc.set_ct_range(ct_min, ct_max)
 		
 
 ## Build table of lights.
@@ -43,6 +63,7 @@ func set_num_lights(n: int) -> void:
 			row.append(new_child)
 			grid.add_child(new_child)
 		row[0].button_pressed = true
+		row[0].toggled.connect(func(_pressed): _update_ct_range())
 		row[1].color = lc.state.preview
 		row[1].gui_input.connect(_swatch_input.bind(idx))
 		row[2].text = "%d" % [idx]
@@ -62,6 +83,20 @@ func _recv_lights_state(lights_state_in: Array):
 		lc.set_state(light_state[0])
 
 
+func _recv_light_update(idx: int, light_in: Dictionary):
+	if idx < num_lights:
+		light_state[idx] = LCTypes.LightState.from_dict(light_in)
+		light_rows[idx][1].color = light_state[idx].preview
+
+
+func _recv_lights_info(lights_info_in: Array):
+	set_num_lights(len(lights_info_in))
+	lights_info = lights_info_in
+	for idx in len(lights_info_in):
+		light_rows[idx][2].text = lights_info_in[idx]["name"]
+	_update_ct_range()
+
+
 func _ready() -> void:
 	# Disable joystick input for GUI
 	var actions := InputMap.get_actions()
@@ -86,4 +121,6 @@ func _ready() -> void:
 		grid.remove_child(child)
 
 	conn.received_lights_state.connect(_recv_lights_state)
+	conn.received_light_update.connect(_recv_light_update)
+	conn.received_lights_info.connect(_recv_lights_info)
 	lc.state_changed.connect(_lc_changed)
@@ -1,8 +1,12 @@
 extends Node
 
 signal received_lights_state(lights: Array)
+signal received_light_update(idx: int, light: Dictionary)
+signal received_lights_info(lights: Array)
 
 const DEFAULT_PORT = 4983
+# Protocol version this client understands, see "hello" in light-protocol.
+const PROTOCOL_VERSION = 1
 
 enum State {
 	IDLE = 0,
@@ -52,8 +56,17 @@ func _process_line(line: PackedByteArray) -> void:
 	var data := JSON.new()
 	data.parse(ustr)
 	print("received: ", data.data)
-	if "state" in data.data:
-		received_lights_state.emit(data.data['state'])
+	match data.data.get("response"):
+		"hello":
+			var hello: Dictionary = data.data['hello']
+			if int(hello['protocol']) != PROTOCOL_VERSION:
+				push_error("Server %s uses protocol version %d, expected %d" % [hello['server'], int(hello['protocol']), PROTOCOL_VERSION])
+		"state":
+			received_lights_state.emit(data.data['state'])
+		"update":
+			received_light_update.emit(int(data.data['idx']), data.data['light'])
+		"describe":
+			received_lights_info.emit(data.data['lights'])
 
 		
 func _recv() -> void:
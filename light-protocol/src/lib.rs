@@ -3,12 +3,131 @@ use serde::{Deserialize, Serialize};
 /* Protocol:
  * in:
  *   {"idx": n, "state": {"mode": ..., "dim": ..., "ct": ..., "gm": ..., "hue": ..., "sat": ... }}
+ *   {"idx": n, "state": {...}, "transition": {"duration": ms, "easing": ...}}
+ *   {"cmd":"scene_save", "name": "..."}
+ *   {"cmd":"scene_recall", "name": "...", "transition": {...}}
+ *   {"cmd":"scene_list"}
+ *   {"cmd":"scene_delete", "name": "..."}
+ *   {"cmd":"cuelist_save", "name": "...", "cues": [{"scene": "...", "transition": {...}, "wait": ms, "label": "..."}, ...]}
+ *   {"cmd":"cuelist_list"}
+ *   {"cmd":"cuelist_delete", "name": "..."}
+ *   {"cmd":"cue_load", "name": "..."}
+ *   {"cmd":"cue_go"}
+ *   {"cmd":"cue_back"}
+ *   {"cmd":"cue_jump", "cue": n}
+ *   {"cmd":"cue_pause"}
+ *   {"cmd":"cue_resume"}
+ *   {"cmd":"cue_stop"}
+ *   {"cmd":"effect_start", "idx": n, "effect": {"type": "...", "speed": hz, "depth": percent}}
+ *   {"cmd":"effect_stop", "idx": n}
+ *   {"cmd":"batch", "lights": [{"idx": n, "state": {...}}, ...], "transition": {...}}
+ *   {"cmd":"batch", "group": "...", "state": {...}, "transition": {...}}
+ *   {"cmd":"get"}
+ *   {"cmd":"get", "idx": n}
+ *   {"cmd":"subscribe", "idxs": [n, ...], "fields": ["dim", ...]}
+ *   {"cmd":"unsubscribe", "idxs": [n, ...]}
+ *   {"cmd":"stats"}
+ *   {"cmd":"refresh"}
+ *
+ * "cmd" defaults to "set", which sets the state of light "idx".
+ *
+ * Every command can have an "id" (any JSON value, usually a number or string),
+ * which is echoed back in the "ok", "err" or other response to that command,
+ * e.g. {"id": 7, "cmd": "scene_list"} -> {"response": "scenes", "id": 7, ...}.
+ * If a line can't be parsed as a command, its "id" is still echoed when possible.
+ *
  * out:
- *   {"response":"err", "error":"..."}
+ *   {"response":"hello", "hello": {"server": "...", "protocol": n, "capabilities": ["...", ...], "num_lights": n,
+ *                                  "modes": [...], "ranges": {"dim": [min, max], "gm": [...], "hue": [...], "sat": [...]}}}
+ *   {"response":"err", "error":"...", "code":"..."}
  *   {"response":"ok"}
- *   {"response":"state", "state": {...}}
+ *   {"response":"state", "state": [{...}, ...]}
+ *   {"response":"state", "idx": n, "light": {...}}
+ *   {"response":"update", "idx": n, "light": {...}}
+ *   {"response":"scenes", "scenes": ["...", ...]}
+ *   {"response":"cuelists", "cuelists": ["...", ...]}
+ *   {"response":"playback", "playback": {"cuelist": "...", "cues": [...], "cue": n, "paused": false}}
+ *   {"response":"describe", "lights": [{"name": ..., "groups": [...], "model": ..., "modes": [...], "ct_range": [min, max]}, ...]}
+ *   {"response":"stats", "stats": [{"sent": n, "acked": n, "failed": n, "retransmits": n, "errors": n}, ...]}
+ *
+ * "hello", "describe", "state" and "playback" are sent to a client when it connects,
+ * in that order.
+ * "update" is sent unsolicited to all other clients that subscribed to the light when
+ * it changes.
+ * "playback" is sent unsolicited to all clients when the cue playback changes.
+ * Unsolicited messages never have an "id". Exactly one response is sent for
+ * every command, in the order the commands were received, so clients that don't
+ * use ids can still match responses to commands by skipping unsolicited messages.
+ *
+ * "hello" describes the server. "protocol" is PROTOCOL_VERSION, which is only increased
+ * for changes that are not backwards compatible; clients should refuse to work with
+ * a server with a different protocol version. Backwards compatible additions are
+ * announced in "capabilities":
+ *   transitions      "transition" in set and scene_recall
+ *   scenes           scene_* commands
+ *   cuelists         cuelist_* and cue_* commands
+ *   effects          effect_* commands
+ *   ids              "id" is echoed in responses
+ *   batch            batch command
+ *   query            get, subscribe and unsubscribe commands
+ *   deltas           relative changes in states ("dim_delta", ...)
+ *   stats            stats command
+ *   refresh          refresh command
+ * "modes" are the modes supported by any light, and "ranges" the valid ranges of
+ * values other than "ct", which depends on the light (see "describe").
+ *
+ * "batch" changes several lights at once: the lights in "lights", and/or all lights
+ * in "group" to "state". Either all changes are made, or none if any is invalid.
+ * The changes start at the same time, and are transmitted back-to-back.
+ *
+ * Instead of an absolute value, a state in a command can have a relative change
+ * "dim_delta", "ct_delta", "gm_delta", "hue_delta" or "sat_delta", e.g.
+ * {"idx": 0, "state": {"dim_delta": 10}}. The result is clamped to the valid range
+ * of the value, except for hue, which wraps around. The light must already have
+ * a value to change. An absolute value in the same state is applied first.
+ *
+ * "get" returns the state of all lights, or of light "idx", as a "state" response.
+ *
+ * Connections start out subscribed to all fields of all lights. "subscribe" subscribes
+ * to the lights in "idxs" (default all lights), replacing the fields subscribed to for
+ * those lights with "fields" (default all fields: mode, dim, ct, gm, hue, sat). An
+ * "update" for a light is only sent if one of its subscribed fields changed; it
+ * always has the complete state of the light. "unsubscribe" unsubscribes from the
+ * lights in "idxs" (default all lights). To only follow some lights, first unsubscribe
+ * from all lights.
+ *
+ * "stats" returns radio delivery statistics for each light since the server started:
+ * the number of commands "sent" to the light, of which "acked" were acknowledged
+ * and "failed" were not, even after retrying; the number of packets that were
+ * "retransmits" of a command; and the number of radio "errors".
+ *
+ * "refresh" sends the last values sent to each light again, for lights that were
+ * switched off or out of range when they were changed.
+ *
+ * A cue recalls a scene with an optional transition. If "wait" is set, the next
+ * cue is started automatically that many milliseconds after the cue started,
+ * otherwise playback waits for "cue_go".
+ *
+ * An effect varies the light around its current state until it is stopped.
+ * Changing the state of the light while an effect runs changes the base of the effect.
+ * Effect types: strobe, pulse, candle, lightning (any mode), police, rainbow, tv (HSI only).
+ *
+ * Error codes:
+ *   parse_error      the command could not be parsed
+ *   unknown_idx      there is no light with the given index
+ *   invalid_range    a field in the state is outside its valid range
+ *   mode_incomplete  the resulting state doesn't have all values for its mode
+ *   unsupported_mode the light doesn't support the requested mode
+ *   unknown_scene    there is no scene with the given name
+ *   unknown_cuelist  there is no cue list with the given name
+ *   unknown_cue      there is no cue to go to
+ *   unknown_group    there are no lights in the given group
+ *   internal_error   the server failed to execute the command
  */
 
+/** Version of the protocol, see "hello". */
+pub const PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum ModeType {
     #[serde(rename = "cct")]
@@ -19,15 +138,114 @@ pub enum ModeType {
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum ResponseType {
+    #[serde(rename = "hello")]
+    Hello,
     #[serde(rename = "err")]
     Err,
     #[serde(rename = "ok")]
     OK,
     #[serde(rename = "state")]
     State,
+    #[serde(rename = "update")]
+    Update,
+    #[serde(rename = "describe")]
+    Describe,
+    #[serde(rename = "scenes")]
+    Scenes,
+    #[serde(rename = "cuelists")]
+    CueLists,
+    #[serde(rename = "playback")]
+    Playback,
+    #[serde(rename = "stats")]
+    Stats,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+pub enum CommandType {
+    #[default]
+    #[serde(rename = "set")]
+    Set,
+    #[serde(rename = "scene_save")]
+    SceneSave,
+    #[serde(rename = "scene_recall")]
+    SceneRecall,
+    #[serde(rename = "scene_list")]
+    SceneList,
+    #[serde(rename = "scene_delete")]
+    SceneDelete,
+    #[serde(rename = "cuelist_save")]
+    CueListSave,
+    #[serde(rename = "cuelist_list")]
+    CueListList,
+    #[serde(rename = "cuelist_delete")]
+    CueListDelete,
+    #[serde(rename = "cue_load")]
+    CueLoad,
+    #[serde(rename = "cue_go")]
+    CueGo,
+    #[serde(rename = "cue_back")]
+    CueBack,
+    #[serde(rename = "cue_jump")]
+    CueJump,
+    #[serde(rename = "cue_pause")]
+    CuePause,
+    #[serde(rename = "cue_resume")]
+    CueResume,
+    #[serde(rename = "cue_stop")]
+    CueStop,
+    #[serde(rename = "effect_start")]
+    EffectStart,
+    #[serde(rename = "effect_stop")]
+    EffectStop,
+    #[serde(rename = "batch")]
+    Batch,
+    #[serde(rename = "get")]
+    Get,
+    #[serde(rename = "subscribe")]
+    Subscribe,
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe,
+    #[serde(rename = "stats")]
+    Stats,
+    #[serde(rename = "refresh")]
+    Refresh,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Model {
+    #[serde(rename = "pavotube_ii_6c")]
+    PavoTubeII6C,
+    #[serde(rename = "fc_60b")]
+    FC60B,
+    #[serde(rename = "fc_120b")]
+    FC120B,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ErrorCode {
+    #[serde(rename = "parse_error")]
+    ParseError,
+    #[serde(rename = "unknown_idx")]
+    UnknownIdx,
+    #[serde(rename = "invalid_range")]
+    InvalidRange,
+    #[serde(rename = "mode_incomplete")]
+    ModeIncomplete,
+    #[serde(rename = "unsupported_mode")]
+    UnsupportedMode,
+    #[serde(rename = "unknown_scene")]
+    UnknownScene,
+    #[serde(rename = "unknown_cuelist")]
+    UnknownCueList,
+    #[serde(rename = "unknown_cue")]
+    UnknownCue,
+    #[serde(rename = "unknown_group")]
+    UnknownGroup,
+    #[serde(rename = "internal_error")]
+    InternalError,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct State {
     pub mode: Option<ModeType>,
     pub dim: Option<u16>,
@@ -35,17 +253,304 @@ pub struct State {
     pub gm: Option<i16>,
     pub hue: Option<u16>,
     pub sat: Option<u16>,
+    /// Relative changes, only in commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dim_delta: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ct_delta: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gm_delta: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hue_delta: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sat_delta: Option<i32>,
+}
+
+/** Field of a light state, to subscribe to. */
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum StateField {
+    #[serde(rename = "mode")]
+    Mode,
+    #[serde(rename = "dim")]
+    Dim,
+    #[serde(rename = "ct")]
+    CT,
+    #[serde(rename = "gm")]
+    GM,
+    #[serde(rename = "hue")]
+    Hue,
+    #[serde(rename = "sat")]
+    Sat,
+}
+
+impl StateField {
+    pub const ALL: [StateField; 6] = [
+        StateField::Mode,
+        StateField::Dim,
+        StateField::CT,
+        StateField::GM,
+        StateField::Hue,
+        StateField::Sat,
+    ];
+
+    /** Whether this field differs between two states. */
+    pub fn differs(&self, a: &State, b: &State) -> bool {
+        match self {
+            StateField::Mode => a.mode != b.mode,
+            StateField::Dim => a.dim != b.dim,
+            StateField::CT => a.ct != b.ct,
+            StateField::GM => a.gm != b.gm,
+            StateField::Hue => a.hue != b.hue,
+            StateField::Sat => a.sat != b.sat,
+        }
+    }
+}
+
+/** Valid ranges of state values, other than "ct". */
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct ValueRanges {
+    pub dim: (u16, u16),
+    pub gm: (i16, i16),
+    pub hue: (u16, u16),
+    pub sat: (u16, u16),
+}
+
+impl Default for ValueRanges {
+    fn default() -> ValueRanges {
+        ValueRanges {
+            dim: (0, 100),
+            gm: (-100, 100),
+            hue: (0, 360),
+            sat: (0, 100),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    /// Name and version of the server software.
+    pub server: String,
+    pub protocol: u32,
+    pub capabilities: Vec<String>,
+    pub num_lights: usize,
+    pub modes: Vec<ModeType>,
+    pub ranges: ValueRanges,
+}
+
+impl Hello {
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/** Radio delivery statistics of a light. */
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+pub struct DeliveryStats {
+    /// Commands sent to the light.
+    pub sent: u64,
+    /// Commands that were acknowledged by the light.
+    pub acked: u64,
+    /// Commands that were not acknowledged, even after retrying.
+    pub failed: u64,
+    /// Packets sent again, for repeats and retries.
+    pub retransmits: u64,
+    /// Packets that could not be sent because of a radio error.
+    pub errors: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LightInfo {
+    pub name: String,
+    pub groups: Vec<String>,
+    pub model: Option<Model>,
+    pub modes: Vec<ModeType>,
+    pub ct_range: (u16, u16),
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+pub enum Easing {
+    #[default]
+    #[serde(rename = "linear")]
+    Linear,
+    #[serde(rename = "ease_in")]
+    EaseIn,
+    #[serde(rename = "ease_out")]
+    EaseOut,
+    #[serde(rename = "ease_in_out")]
+    EaseInOut,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Transition {
+    /// Duration in milliseconds.
+    pub duration: u32,
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+/** Change to one light in a batch. */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LightChange {
+    pub idx: u16,
+    pub state: State,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cue {
+    /// Scene to recall.
+    pub scene: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+    /// Time in milliseconds after the start of this cue to start the next cue automatically.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Playback {
+    /// Name of the loaded cue list.
+    pub cuelist: Option<String>,
+    pub cues: Vec<Cue>,
+    /// Index of the current cue, if playback has started.
+    pub cue: Option<usize>,
+    pub paused: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum EffectType {
+    #[serde(rename = "strobe")]
+    Strobe,
+    #[serde(rename = "pulse")]
+    Pulse,
+    #[serde(rename = "candle")]
+    Candle,
+    #[serde(rename = "lightning")]
+    Lightning,
+    #[serde(rename = "police")]
+    Police,
+    #[serde(rename = "rainbow")]
+    Rainbow,
+    #[serde(rename = "tv")]
+    TV,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Effect {
+    #[serde(rename = "type")]
+    pub effect_type: EffectType,
+    /// Speed in cycles per second.
+    #[serde(default = "default_effect_speed")]
+    pub speed: f32,
+    /// Depth in percent, how far the effect deviates from the light state.
+    #[serde(default = "default_effect_depth")]
+    pub depth: u16,
+}
+
+fn default_effect_speed() -> f32 {
+    1.0
+}
+
+fn default_effect_depth() -> u16 {
+    100
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Command {
-    pub idx: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    #[serde(default)]
+    pub cmd: CommandType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idx: Option<u16>,
+    #[serde(default)]
     pub state: State,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cues: Option<Vec<Cue>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cue: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<Effect>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lights: Option<Vec<LightChange>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idxs: Option<Vec<u16>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<StateField>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Response {
     pub response: ResponseType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
     pub error: Option<String>,
     pub state: Option<Vec<State>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idx: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<State>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lights: Option<Vec<LightInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scenes: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cuelists: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub playback: Option<Playback>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hello: Option<Hello>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Vec<DeliveryStats>>,
+}
+
+impl Command {
+    /** Create a command of the given type with all other fields unset. */
+    pub fn new(cmd: CommandType) -> Command {
+        Command {
+            id: None,
+            cmd,
+            idx: None,
+            state: State::default(),
+            transition: None,
+            name: None,
+            cues: None,
+            cue: None,
+            effect: None,
+            lights: None,
+            group: None,
+            idxs: None,
+            fields: None,
+        }
+    }
+}
+
+impl Response {
+    /** Create a response of the given type with all other fields unset. */
+    pub fn new(response: ResponseType) -> Response {
+        Response {
+            response,
+            id: None,
+            error: None,
+            state: None,
+            code: None,
+            idx: None,
+            light: None,
+            lights: None,
+            scenes: None,
+            cuelists: None,
+            playback: None,
+            hello: None,
+            stats: None,
+        }
+    }
 }
@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use log::debug;
+use rf24::radio::prelude::EsbRadio;
+use serde::Serialize;
+
+use crate::configuration;
+use crate::effects::Rng;
+use crate::nanlite;
+
+pub type BackendError = Box<dyn Error + Send + Sync>;
+
+/** Radio backend that the lights task transmits packets through.
+ */
+pub trait LightBackend: Send {
+    /** Transmit a single payload to the given radio address.
+     * Returns whether the packet was acknowledged by the receiver.
+     */
+    fn transmit(&mut self, address: &[u8], payload: &[u8]) -> Result<bool, BackendError>;
+}
+
+/** Backend that sends packets through a nRF24L01+ module on SPI.
+ */
+pub struct RF24Backend {
+    rf24: nanlite::RF24Radio,
+}
+
+impl LightBackend for RF24Backend {
+    fn transmit(&mut self, address: &[u8], payload: &[u8]) -> Result<bool, BackendError> {
+        self.rf24
+            .as_tx(Some(address))
+            .map_err(|e| format!("Error setting TX address: {:?}", e))?;
+        Ok(self
+            .rf24
+            .send(payload, false)
+            .map_err(|e| format!("Error sending packet: {:?}", e))?)
+    }
+}
+
+/** Packet as recorded by the simulated backend. */
+#[derive(Serialize, Debug)]
+struct SimulatedPacket<'a> {
+    address: &'a [u8],
+    payload: &'a [u8],
+}
+
+/** Backend that doesn't need any hardware. Every packet that would have been
+ * sent is logged, and optionally appended to a JSONL packet log. A fraction of
+ * the packets can be reported as not acknowledged, to simulate a noisy radio.
+ */
+pub struct SimulatedBackend {
+    packet_log: Option<File>,
+    packet_loss: f32,
+    rng: Rng,
+}
+
+impl LightBackend for SimulatedBackend {
+    fn transmit(&mut self, address: &[u8], payload: &[u8]) -> Result<bool, BackendError> {
+        let packet = SimulatedPacket { address, payload };
+        debug!("Simulated packet: {:?}", packet);
+        if let Some(packet_log) = &mut self.packet_log {
+            let line = serde_json::to_string(&packet)? + "\n";
+            packet_log.write_all(line.as_bytes())?;
+        }
+        Ok(self.rng.random() >= self.packet_loss)
+    }
+}
+
+/** Open the backend selected in the hardware configuration.
+ */
+pub fn open(config: &configuration::Hardware) -> Result<Box<dyn LightBackend>, BackendError> {
+    match config.backend {
+        configuration::Backend::RF24 => {
+            let rf24 = nanlite::rf24_init(&config.device, config.nrf24_ce_gpio)?;
+            Ok(Box::new(RF24Backend { rf24 }))
+        }
+        configuration::Backend::Simulated => {
+            let packet_log = match &config.packet_log {
+                Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+                None => None,
+            };
+            Ok(Box::new(SimulatedBackend {
+                packet_log,
+                packet_loss: config.packet_loss,
+                rng: Rng::new(0x2545f491),
+            }))
+        }
+    }
+}
@@ -1,19 +1,203 @@
+use light_protocol::{LightInfo, ModeType, Model};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::{dmx, models};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Network {
     pub bind_addr: String,
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+pub enum Backend {
+    #[default]
+    #[serde(rename = "rf24")]
+    RF24,
+    #[serde(rename = "simulated")]
+    Simulated,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Hardware {
+    #[serde(default)]
+    pub backend: Backend,
     pub device: String,
     pub nrf24_ce_gpio: u32,
+    #[serde(default)]
+    pub packet_log: Option<PathBuf>,
+    #[serde(default = "default_fade_interval_ms")]
+    pub fade_interval_ms: u64,
+    /// Number of times each packet is sent.
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+    /// Number of times a packet is sent again if it wasn't acknowledged.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Time before the first retry, doubled for each further retry.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    /// Maximum number of commands sent per second, over all lights.
+    #[serde(default = "default_max_send_rate")]
+    pub max_send_rate: u32,
+    /// Time after which the last command sent to a light is sent again.
+    #[serde(default)]
+    pub refresh_interval_ms: Option<u64>,
+    /// Fraction of packets that the simulated backend drops, 0.0..1.0.
+    #[serde(default)]
+    pub packet_loss: f32,
+}
+
+fn default_fade_interval_ms() -> u64 {
+    50
+}
+
+fn default_max_send_rate() -> u32 {
+    200
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    5
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum DmxProtocol {
+    #[serde(rename = "artnet")]
+    ArtNet,
+    #[serde(rename = "sacn")]
+    SACN,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Dmx {
+    pub protocol: DmxProtocol,
+    #[serde(default)]
+    pub bind_addr: Option<String>,
+    pub universe: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Osc {
+    pub bind_addr: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Http {
+    pub bind_addr: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mqtt {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Client ID, also used to make the Home Assistant IDs unique.
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    #[serde(default = "default_mqtt_base_topic")]
+    pub base_topic: String,
+    #[serde(default = "default_mqtt_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "light-server".to_string()
+}
+
+fn default_mqtt_base_topic() -> String {
+    "light-server".to_string()
+}
+
+fn default_mqtt_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+/** DMX patch of a light. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DmxPatch {
+    /// Start address, 1..512.
+    pub address: u16,
+    pub personality: dmx::Personality,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Light {
     pub address: u16,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub model: Option<Model>,
+    #[serde(default)]
+    pub modes: Option<Vec<ModeType>>,
+    #[serde(default)]
+    pub ct_range: Option<(u16, u16)>,
+    #[serde(default)]
+    pub ct_curve: Option<models::CtCurve>,
+    #[serde(default)]
+    pub dmx: Option<DmxPatch>,
+}
+
+impl Light {
+    /** Supported modes of the light, from the configuration or its model. */
+    pub fn modes(&self) -> Vec<ModeType> {
+        match &self.modes {
+            Some(modes) => modes.clone(),
+            None => models::model_info(self.model).modes.to_vec(),
+        }
+    }
+
+    /** Color temperature range of the light in Kelvin, from the configuration or its model. */
+    pub fn ct_range(&self) -> (u16, u16) {
+        self.ct_range
+            .unwrap_or(models::model_info(self.model).ct_range)
+    }
+
+    /** Color temperature mapping curve, from the configuration or its model. */
+    pub fn ct_curve(&self) -> models::CtCurve {
+        self.ct_curve
+            .unwrap_or(models::model_info(self.model).ct_curve)
+    }
+
+    /** Describe light for clients, filling in defaults for unset values. */
+    pub fn info(&self, idx: usize) -> LightInfo {
+        LightInfo {
+            name: self
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("Light #{}", idx + 1)),
+            groups: self.groups.clone(),
+            model: self.model,
+            modes: self.modes(),
+            ct_range: self.ct_range(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Persistence {
+    pub state_file: PathBuf,
+    #[serde(default)]
+    pub retransmit: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -21,4 +205,69 @@ pub struct Configuration {
     pub network: Network,
     pub hardware: Hardware,
     pub lights: Vec<Light>,
+    #[serde(default)]
+    pub persistence: Option<Persistence>,
+    #[serde(default)]
+    pub scenes_file: Option<PathBuf>,
+    #[serde(default)]
+    pub cues_file: Option<PathBuf>,
+    #[serde(default)]
+    pub dmx: Option<Dmx>,
+    #[serde(default)]
+    pub osc: Option<Osc>,
+    #[serde(default)]
+    pub http: Option<Http>,
+    #[serde(default)]
+    pub mqtt: Option<Mqtt>,
+}
+
+impl Configuration {
+    /** Check configuration for values that can't be used. */
+    pub fn validate(&self) -> Result<(), String> {
+        if self.hardware.fade_interval_ms == 0 {
+            return Err("fade_interval_ms must be larger than 0".to_string());
+        }
+        if self.hardware.max_send_rate == 0 {
+            return Err("max_send_rate must be larger than 0".to_string());
+        }
+        if self.hardware.refresh_interval_ms == Some(0) {
+            return Err("refresh_interval_ms must be larger than 0".to_string());
+        }
+        if self.hardware.repeat == 0 {
+            return Err("repeat must be larger than 0".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.hardware.packet_loss) {
+            return Err("packet_loss must be in the range 0.0..1.0".to_string());
+        }
+        for (idx, light) in self.lights.iter().enumerate() {
+            let (ct_min, ct_max) = light.ct_range();
+            if ct_min >= ct_max {
+                return Err(format!(
+                    "light {}: invalid ct_range {}..{}",
+                    idx, ct_min, ct_max
+                ));
+            }
+            if let Some(patch) = &light.dmx {
+                let end = patch.address as usize + patch.personality.channels() - 1;
+                if patch.address == 0 || end > 512 {
+                    return Err(format!(
+                        "light {}: DMX address {} out of range 1..512",
+                        idx, patch.address
+                    ));
+                }
+            }
+        }
+        if let Some(dmx) = &self.dmx {
+            let max_universe = match dmx.protocol {
+                DmxProtocol::ArtNet => 32767,
+                DmxProtocol::SACN => 63999,
+            };
+            if (dmx.protocol == DmxProtocol::SACN && dmx.universe == 0)
+                || dmx.universe > max_universe
+            {
+                return Err(format!("invalid DMX universe {}", dmx.universe));
+            }
+        }
+        Ok(())
+    }
 }
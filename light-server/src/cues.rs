@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
+
+use light_protocol::{Cue, ErrorCode, Playback};
+
+use crate::{CommandError, Shared, recall_scene};
+
+/** Command to playback thread. */
+#[derive(Debug, Clone)]
+pub enum PlaybackCommand {
+    /// Load a cue list, stopping playback of the previous one.
+    Load(String, Vec<Cue>),
+    Go,
+    Back,
+    Jump(usize),
+    Pause,
+    Resume,
+    Stop,
+}
+
+/** Playback command, with a channel for the result. */
+pub type PlaybackRequest = (PlaybackCommand, oneshot::Sender<Result<(), CommandError>>);
+
+/** Time at which the next cue starts automatically. */
+enum Follow {
+    None,
+    At(Instant),
+    /// Paused, with the time that was left.
+    Paused(Duration),
+}
+
+/** Start a cue: recall its scene and schedule the next cue if it has a wait time. */
+async fn start_cue(shared: &Shared, playback: &mut Playback, idx: usize) -> Follow {
+    let Some(cue) = playback.cues.get(idx) else {
+        return Follow::None;
+    };
+    info!(
+        "Cue {} ({:?})",
+        idx + 1,
+        cue.label.as_deref().unwrap_or(&cue.scene)
+    );
+    playback.cue = Some(idx);
+    playback.paused = false;
+    if let Err(err) = recall_scene(shared, &cue.scene, cue.transition).await {
+        warn!("Error in cue {}: {}", idx + 1, err.message);
+    }
+    match cue.wait {
+        Some(wait) if idx + 1 < playback.cues.len() => {
+            Follow::At(Instant::now() + Duration::from_millis(wait as u64))
+        }
+        _ => Follow::None,
+    }
+}
+
+/** Error for a cue that doesn't exist. */
+fn no_cue() -> CommandError {
+    CommandError::new(ErrorCode::UnknownCue, "no cue to go to")
+}
+
+/** Execute a playback command. */
+async fn handle_playback_command(
+    shared: &Shared,
+    playback: &mut Playback,
+    follow: &mut Follow,
+    cmd: PlaybackCommand,
+) -> Result<(), CommandError> {
+    match cmd {
+        PlaybackCommand::Load(name, cues) => {
+            *playback = Playback {
+                cuelist: Some(name),
+                cues,
+                cue: None,
+                paused: false,
+            };
+            *follow = Follow::None;
+        }
+        PlaybackCommand::Go => {
+            let next = playback.cue.map_or(0, |cue| cue + 1);
+            if next >= playback.cues.len() {
+                return Err(no_cue());
+            }
+            *follow = start_cue(shared, playback, next).await;
+        }
+        PlaybackCommand::Back => {
+            let Some(prev) = playback.cue.and_then(|cue| cue.checked_sub(1)) else {
+                return Err(no_cue());
+            };
+            *follow = start_cue(shared, playback, prev).await;
+        }
+        PlaybackCommand::Jump(idx) => {
+            if idx >= playback.cues.len() {
+                return Err(no_cue());
+            }
+            *follow = start_cue(shared, playback, idx).await;
+        }
+        PlaybackCommand::Pause => {
+            if let Follow::At(deadline) = *follow {
+                *follow = Follow::Paused(deadline.saturating_duration_since(Instant::now()));
+            }
+            playback.paused = playback.cue.is_some();
+        }
+        PlaybackCommand::Resume => {
+            if let Follow::Paused(remaining) = *follow {
+                *follow = Follow::At(Instant::now() + remaining);
+            }
+            playback.paused = false;
+        }
+        PlaybackCommand::Stop => {
+            playback.cue = None;
+            playback.paused = false;
+            *follow = Follow::None;
+        }
+    }
+    Ok(())
+}
+
+/** Task that plays back the loaded cue list, on its own clock, so that it
+ * continues independently of clients.
+ */
+pub async fn playback_task(
+    shared: Arc<Shared>,
+    mut rx: mpsc::Receiver<PlaybackRequest>,
+    status: watch::Sender<Playback>,
+) {
+    info!("Playback thread running");
+    let mut playback = Playback::default();
+    let mut follow = Follow::None;
+
+    loop {
+        let deadline = match follow {
+            Follow::At(deadline) => Some(deadline),
+            _ => None,
+        };
+        tokio::select! {
+            req = rx.recv() => {
+                let Some((cmd, reply)) = req else {
+                    break;
+                };
+                debug!("Playback: {:?}", cmd);
+                let result = handle_playback_command(&shared, &mut playback, &mut follow, cmd).await;
+                // The client might have gone away already.
+                let _ = reply.send(result);
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                let next = playback.cue.map_or(0, |cue| cue + 1);
+                follow = start_cue(&shared, &mut playback, next).await;
+            }
+        }
+        status.send_if_modified(|old| {
+            if *old != playback {
+                *old = playback.clone();
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
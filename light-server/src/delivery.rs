@@ -0,0 +1,281 @@
+/* Delivery of light commands over the radio.
+ *
+ * Packets can get lost, especially with other 2.4 GHz traffic around. Each command
+ * is sent `repeat` times, and if none of those packets is acknowledged by the light,
+ * it is sent again up to `retries` times with an increasing delay in between.
+ *
+ * Commands can come in faster than they can be sent. Only the latest command for
+ * each light is kept, and lights take turns, so that the radio never lags behind.
+ * Retries wait their turn like any other command, and are dropped when a newer
+ * command for the light comes in, so that an unresponsive light doesn't hold up
+ * the others.
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use light_protocol::DeliveryStats;
+use log::{debug, warn};
+use tokio::time::Instant;
+
+use crate::backend::{BackendError, LightBackend};
+use crate::{LightCommand, configuration, nanlite};
+
+/** Command waiting to be sent. */
+#[derive(Debug, Copy, Clone)]
+struct Waiting {
+    cmd: LightCommand,
+    /// Number of times the command was sent without being acknowledged.
+    retry: u32,
+    /// Time before which a retry must not be sent.
+    not_before: Option<Instant>,
+}
+
+/** Commands waiting to be sent, the latest one for each light address. */
+#[derive(Debug, Default)]
+pub struct Pending {
+    commands: HashMap<u16, Waiting>,
+    /// Addresses with a waiting command, in the order they are sent.
+    queue: VecDeque<u16>,
+}
+
+impl Pending {
+    fn insert(&mut self, address: u16, waiting: Waiting) {
+        if self.commands.insert(address, waiting).is_none() {
+            self.queue.push_back(address);
+        }
+    }
+
+    /** Add a command, replacing the command waiting for the same light, if any.
+     * A light keeps its place in the queue when its command is replaced.
+     */
+    pub fn push(&mut self, address: u16, cmd: LightCommand) {
+        let waiting = Waiting {
+            cmd,
+            retry: 0,
+            not_before: None,
+        };
+        self.insert(address, waiting);
+    }
+
+    /** Add a command, unless a command for the same light is already waiting. */
+    pub fn push_if_idle(&mut self, address: u16, cmd: LightCommand) {
+        if !self.commands.contains_key(&address) {
+            self.push(address, cmd);
+        }
+    }
+
+    /** Add a command to be sent again, not before the given time. A command for the
+     * same light that is already waiting is newer, so the retry is dropped then.
+     */
+    fn push_retry(&mut self, address: u16, cmd: LightCommand, retry: u32, not_before: Instant) {
+        if !self.commands.contains_key(&address) {
+            let waiting = Waiting {
+                cmd,
+                retry,
+                not_before: Some(not_before),
+            };
+            self.insert(address, waiting);
+        }
+    }
+
+    /** Take the command of the light whose turn it is, skipping retries that are
+     * not due yet at `now`. Returns the address, the command and the retry number.
+     */
+    pub fn pop(&mut self, now: Instant) -> Option<(u16, LightCommand, u32)> {
+        let pos = self.queue.iter().position(|address| {
+            self.commands[address]
+                .not_before
+                .is_none_or(|not_before| not_before <= now)
+        })?;
+        let address = self.queue.remove(pos)?;
+        let waiting = self.commands.remove(&address)?;
+        Some((address, waiting.cmd, waiting.retry))
+    }
+
+    /** Earliest time at which a command can be taken, if any is waiting. */
+    pub fn next_due(&self, now: Instant) -> Option<Instant> {
+        self.commands
+            .values()
+            .map(|waiting| waiting.not_before.unwrap_or(now))
+            .min()
+    }
+}
+
+/** Outcome of sending a command to a light once. */
+#[derive(Debug, Default)]
+pub struct Delivery {
+    /// Whether this was the first time the command was sent.
+    pub first: bool,
+    pub acked: bool,
+    /// Whether the command was not acknowledged, and there are no retries left.
+    pub failed: bool,
+    /// Packets sent after the first one.
+    pub retransmits: u64,
+    pub errors: u64,
+}
+
+impl Delivery {
+    /** Add the outcome to the statistics of a light. */
+    pub fn record(&self, stats: &mut DeliveryStats) {
+        if self.first {
+            stats.sent += 1;
+        }
+        if self.acked {
+            stats.acked += 1;
+        }
+        if self.failed {
+            stats.failed += 1;
+        }
+        stats.retransmits += self.retransmits;
+        stats.errors += self.errors;
+    }
+}
+
+/** Send a single packet for a command. Returns whether it was acknowledged. */
+fn transmit(
+    backend: &mut dyn LightBackend,
+    address: u16,
+    cmd: LightCommand,
+) -> Result<bool, BackendError> {
+    match cmd {
+        LightCommand::CCT(intensity, cct, gm) => {
+            nanlite::set_intensity_cct_gm(backend, address, intensity, cct, gm)
+        }
+        LightCommand::HSI(hue, sat, intensity) => {
+            nanlite::set_hue_sat_intensity(backend, address, hue, sat, intensity)
+        }
+    }
+}
+
+/** Send a command to the light with the given address. If it is not acknowledged,
+ * it is put back into `pending` to be retried after a delay, unless the retries are
+ * used up. Radio errors are counted, not returned.
+ */
+pub fn send(
+    backend: &mut dyn LightBackend,
+    config: &configuration::Hardware,
+    pending: &mut Pending,
+    address: u16,
+    cmd: LightCommand,
+    retry: u32,
+) -> Delivery {
+    let mut delivery = Delivery {
+        first: retry == 0,
+        ..Delivery::default()
+    };
+    if retry > 0 {
+        debug!("Retrying {:?} to address {} ({})", cmd, address, retry);
+    }
+    for n in 0..config.repeat {
+        if retry > 0 || n > 0 {
+            delivery.retransmits += 1;
+        }
+        match transmit(backend, address, cmd) {
+            Ok(acked) => delivery.acked |= acked,
+            Err(err) => {
+                warn!("Error sending to address {}: {}", address, err);
+                delivery.errors += 1;
+            }
+        }
+    }
+    if delivery.acked {
+        return delivery;
+    }
+    if retry < config.retries {
+        let backoff = Duration::from_millis(config.retry_backoff_ms)
+            .saturating_mul(2u32.saturating_pow(retry));
+        pending.push_retry(address, cmd, retry + 1, Instant::now() + backoff);
+    } else {
+        debug!("Address {} did not acknowledge {:?}", address, cmd);
+        delivery.failed = true;
+    }
+    delivery
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cct(intensity: u8) -> LightCommand {
+        LightCommand::CCT(intensity, 50, 50)
+    }
+
+    /** Take all waiting commands that are due at `now`, in order. */
+    fn drain(pending: &mut Pending, now: Instant) -> Vec<(u16, LightCommand)> {
+        std::iter::from_fn(|| pending.pop(now))
+            .map(|(address, cmd, _)| (address, cmd))
+            .collect()
+    }
+
+    #[test]
+    fn latest_command_replaces_earlier() {
+        let mut pending = Pending::default();
+        pending.push(1, cct(10));
+        pending.push(1, cct(20));
+        assert_eq!(drain(&mut pending, Instant::now()), [(1, cct(20))]);
+    }
+
+    #[test]
+    fn light_keeps_its_place() {
+        let mut pending = Pending::default();
+        pending.push(1, cct(10));
+        pending.push(2, cct(10));
+        pending.push(1, cct(20));
+        assert_eq!(
+            drain(&mut pending, Instant::now()),
+            [(1, cct(20)), (2, cct(10))]
+        );
+    }
+
+    #[test]
+    fn lights_alternate() {
+        let mut pending = Pending::default();
+        let now = Instant::now();
+        pending.push(1, cct(10));
+        pending.push(2, cct(10));
+        assert_eq!(pending.pop(now), Some((1, cct(10), 0)));
+        // Light 1 changes again while light 2 is waiting, light 2 goes first.
+        pending.push(1, cct(20));
+        assert_eq!(drain(&mut pending, now), [(2, cct(10)), (1, cct(20))]);
+    }
+
+    #[test]
+    fn push_if_idle_does_not_overwrite() {
+        let mut pending = Pending::default();
+        pending.push(1, cct(10));
+        pending.push_if_idle(1, cct(20));
+        pending.push_if_idle(2, cct(30));
+        assert_eq!(
+            drain(&mut pending, Instant::now()),
+            [(1, cct(10)), (2, cct(30))]
+        );
+    }
+
+    #[test]
+    fn retry_waits_for_deadline() {
+        let mut pending = Pending::default();
+        let now = Instant::now();
+        let later = now + Duration::from_millis(100);
+        pending.push_retry(1, cct(10), 1, later);
+        pending.push(2, cct(10));
+        assert_eq!(pending.next_due(now), Some(now));
+        assert_eq!(drain(&mut pending, now), [(2, cct(10))]);
+        assert_eq!(pending.next_due(now), Some(later));
+        assert_eq!(pending.pop(later), Some((1, cct(10), 1)));
+        assert_eq!(pending.next_due(now), None);
+    }
+
+    #[test]
+    fn retry_dropped_for_newer_command() {
+        let mut pending = Pending::default();
+        let now = Instant::now();
+        pending.push(1, cct(20));
+        pending.push_retry(1, cct(10), 1, now);
+        assert_eq!(pending.pop(now), Some((1, cct(20), 0)));
+        // A newer command replaces a waiting retry, and is sent right away.
+        pending.push_retry(1, cct(10), 1, now + Duration::from_secs(1));
+        pending.push(1, cct(30));
+        assert_eq!(pending.pop(now), Some((1, cct(30), 0)));
+    }
+}
@@ -0,0 +1,294 @@
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+use light_protocol::{ModeType, State};
+
+use crate::{Shared, apply_state, configuration};
+
+/** UDP port for Art-Net. */
+pub const ARTNET_PORT: u16 = 6454;
+/** UDP port for sACN (E1.31). */
+pub const SACN_PORT: u16 = 5568;
+
+/** Mapping of DMX channels to light values. */
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Personality {
+    /// Dimmer, color temperature, green/magenta (CCT mode).
+    #[serde(rename = "dim_ct_gm")]
+    DimCtGm,
+    /// Dimmer, hue, saturation (HSI mode).
+    #[serde(rename = "dim_hue_sat")]
+    DimHueSat,
+    /// Mode (0..127 CCT, 128..255 HSI), dimmer, color temperature, green/magenta, hue, saturation.
+    #[serde(rename = "full")]
+    Full,
+}
+
+/** Scale a DMX value 0..255 to min..max. */
+fn scale(value: u8, min: i32, max: i32) -> i32 {
+    min + ((value as i32) * (max - min) + 127) / 255
+}
+
+impl Personality {
+    /** Number of DMX channels used. */
+    pub fn channels(&self) -> usize {
+        match self {
+            Personality::DimCtGm | Personality::DimHueSat => 3,
+            Personality::Full => 6,
+        }
+    }
+
+    /** Convert the light's DMX channels to a light state. */
+    pub fn state(&self, light: &configuration::Light, data: &[u8]) -> State {
+        let (ct_min, ct_max) = light.ct_range();
+        let dim = |v| Some(scale(v, 0, 100) as u16);
+        let ct = |v| Some(scale(v, ct_min as i32, ct_max as i32) as u16);
+        let gm = |v| Some(scale(v, -100, 100) as i16);
+        let hue = |v| Some(scale(v, 0, 360) as u16);
+        let sat = |v| Some(scale(v, 0, 100) as u16);
+        match self {
+            Personality::DimCtGm => State {
+                mode: Some(ModeType::CCT),
+                dim: dim(data[0]),
+                ct: ct(data[1]),
+                gm: gm(data[2]),
+                ..State::default()
+            },
+            Personality::DimHueSat => State {
+                mode: Some(ModeType::HSI),
+                dim: dim(data[0]),
+                hue: hue(data[1]),
+                sat: sat(data[2]),
+                ..State::default()
+            },
+            Personality::Full => State {
+                mode: Some(if data[0] < 128 {
+                    ModeType::CCT
+                } else {
+                    ModeType::HSI
+                }),
+                dim: dim(data[1]),
+                ct: ct(data[2]),
+                gm: gm(data[3]),
+                hue: hue(data[4]),
+                sat: sat(data[5]),
+                ..State::default()
+            },
+        }
+    }
+}
+
+/** Parse an Art-Net ArtDmx packet. Returns the universe (port address) and DMX data. */
+fn parse_artnet(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 18 || &packet[0..8] != b"Art-Net\0" {
+        return None;
+    }
+    // OpOutput / ArtDmx, little endian.
+    if u16::from_le_bytes([packet[8], packet[9]]) != 0x5000 {
+        return None;
+    }
+    let universe = u16::from_le_bytes([packet[14], packet[15]]) & 0x7fff;
+    let length = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+    let data = packet.get(18..18 + length)?;
+    Some((universe, data))
+}
+
+/** Parse a sACN (E1.31) data packet. Returns the universe and DMX data. */
+fn parse_sacn(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 126 || &packet[4..16] != b"ASC-E1.17\0\0\0" {
+        return None;
+    }
+    // Root layer vector VECTOR_ROOT_E131_DATA, framing layer vector VECTOR_E131_DATA_PACKET.
+    if packet[18..22] != [0, 0, 0, 4] || packet[40..44] != [0, 0, 0, 2] {
+        return None;
+    }
+    // Ignore preview data.
+    if packet[112] & 0x80 != 0 {
+        return None;
+    }
+    let universe = u16::from_be_bytes([packet[113], packet[114]]);
+    // Property values, including the start code.
+    let count = u16::from_be_bytes([packet[123], packet[124]]) as usize;
+    if count == 0 || packet[125] != 0 {
+        // Not DMX data (non-zero start code).
+        return None;
+    }
+    let data = packet.get(126..125 + count)?;
+    Some((universe, data))
+}
+
+/** Task that receives DMX over Art-Net or sACN, and sets the patched lights.
+ * A light is only changed when its DMX channels change, so that other clients
+ * can still control it in between.
+ */
+pub async fn dmx_task(shared: Arc<Shared>, config: configuration::Dmx) {
+    let default_port = match config.protocol {
+        configuration::DmxProtocol::ArtNet => ARTNET_PORT,
+        configuration::DmxProtocol::SACN => SACN_PORT,
+    };
+    let bind_addr = config
+        .bind_addr
+        .clone()
+        .unwrap_or_else(|| format!("0.0.0.0:{}", default_port));
+    let socket = match UdpSocket::bind(&bind_addr).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("Error binding DMX socket {}: {}", bind_addr, err);
+            return;
+        }
+    };
+    if config.protocol == configuration::DmxProtocol::SACN {
+        // sACN is usually sent to a multicast group per universe.
+        let [hi, lo] = config.universe.to_be_bytes();
+        let group = Ipv4Addr::new(239, 255, hi, lo);
+        if let Err(err) = socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED) {
+            warn!("Error joining multicast group {}: {}", group, err);
+        }
+    }
+    info!(
+        "Receiving {:?} universe {} on {}",
+        config.protocol, config.universe, bind_addr
+    );
+
+    // Last DMX state of each light.
+    let mut last: Vec<Option<State>> = vec![None; shared.light_config.len()];
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(err) => {
+                warn!("Error receiving DMX: {}", err);
+                continue;
+            }
+        };
+        let packet = match config.protocol {
+            configuration::DmxProtocol::ArtNet => parse_artnet(&buf[..len]),
+            configuration::DmxProtocol::SACN => parse_sacn(&buf[..len]),
+        };
+        let Some((universe, data)) = packet else {
+            continue;
+        };
+        if universe != config.universe {
+            continue;
+        }
+
+        let mut fade_reqs = vec![];
+        for (idx, light) in shared.light_config.iter().enumerate() {
+            let Some(patch) = &light.dmx else {
+                continue;
+            };
+            let start = patch.address as usize - 1;
+            let Some(channels) = data.get(start..start + patch.personality.channels()) else {
+                continue;
+            };
+            let state = patch.personality.state(light, channels);
+            if last[idx].as_ref() == Some(&state) {
+                continue;
+            }
+            match apply_state(&shared, None, idx as u16, &state, None) {
+                Ok(fade_req) => fade_reqs.push(fade_req),
+                Err(err) => debug!("DMX for light {}: {:?}", idx, err),
+            }
+            last[idx] = Some(state);
+        }
+        if !fade_reqs.is_empty() {
+            shared.fade_tx.send(fade_reqs).await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artnet(universe: u16, data: &[u8]) -> Vec<u8> {
+        let mut packet = b"Art-Net\0".to_vec();
+        packet.extend_from_slice(&0x5000u16.to_le_bytes());
+        // Protocol version, sequence, physical.
+        packet.extend_from_slice(&[0, 14, 0, 0]);
+        packet.extend_from_slice(&universe.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn sacn(universe: u16, start_code: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; 126];
+        packet[4..16].copy_from_slice(b"ASC-E1.17\0\0\0");
+        packet[18..22].copy_from_slice(&[0, 0, 0, 4]);
+        packet[40..44].copy_from_slice(&[0, 0, 0, 2]);
+        packet[113..115].copy_from_slice(&universe.to_be_bytes());
+        packet[123..125].copy_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+        packet[125] = start_code;
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn artnet_valid() {
+        let packet = artnet(3, &[10, 20, 30]);
+        assert_eq!(parse_artnet(&packet), Some((3, &[10, 20, 30][..])));
+    }
+
+    #[test]
+    fn artnet_truncated() {
+        let packet = artnet(3, &[10, 20, 30]);
+        for len in 0..packet.len() {
+            assert_eq!(parse_artnet(&packet[..len]), None, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn artnet_bad_length() {
+        let mut packet = artnet(3, &[10, 20, 30]);
+        packet[16..18].copy_from_slice(&0xffffu16.to_be_bytes());
+        assert_eq!(parse_artnet(&packet), None);
+    }
+
+    #[test]
+    fn artnet_wrong_opcode() {
+        let mut packet = artnet(3, &[10, 20, 30]);
+        packet[8..10].copy_from_slice(&0x2000u16.to_le_bytes());
+        assert_eq!(parse_artnet(&packet), None);
+    }
+
+    #[test]
+    fn sacn_valid() {
+        let packet = sacn(7, 0, &[10, 20, 30]);
+        assert_eq!(parse_sacn(&packet), Some((7, &[10, 20, 30][..])));
+    }
+
+    #[test]
+    fn sacn_truncated() {
+        let packet = sacn(7, 0, &[10, 20, 30]);
+        for len in 0..packet.len() {
+            assert_eq!(parse_sacn(&packet[..len]), None, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn sacn_bad_length() {
+        let mut packet = sacn(7, 0, &[10, 20, 30]);
+        packet[123..125].copy_from_slice(&0xffffu16.to_be_bytes());
+        assert_eq!(parse_sacn(&packet), None);
+        packet[123..125].copy_from_slice(&0u16.to_be_bytes());
+        assert_eq!(parse_sacn(&packet), None);
+    }
+
+    #[test]
+    fn sacn_non_zero_start_code() {
+        let packet = sacn(7, 0xdd, &[10, 20, 30]);
+        assert_eq!(parse_sacn(&packet), None);
+    }
+
+    #[test]
+    fn sacn_preview() {
+        let mut packet = sacn(7, 0, &[10, 20, 30]);
+        packet[112] = 0x80;
+        assert_eq!(parse_sacn(&packet), None);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use log::{debug, error, info};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use light_protocol::{Effect, EffectType};
+
+use crate::LightCommand;
+
+/** Request to the effects thread. */
+#[derive(Debug, Clone)]
+pub enum EffectRequest {
+    /// Start an effect on a light, with the command last sent to the light, if known.
+    Start(u16, Effect, Option<LightCommand>),
+    Stop(u16),
+}
+
+/** Small pseudo-random number generator (xorshift32) for flickering effects. */
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        Rng(seed | 1)
+    }
+
+    /** Random value 0.0..1.0. */
+    pub fn random(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/** Effect running on a light. */
+struct Running {
+    effect: Effect,
+    start: Instant,
+    rng: Rng,
+    /// Current level of random effects, 0.0..1.0.
+    level: f32,
+    /// Level that random effects move towards.
+    target: f32,
+    /// Time at which random effects pick a new target.
+    next_target: Instant,
+    /// Last command sent for this effect, to avoid sending duplicates.
+    last_cmd: Option<LightCommand>,
+}
+
+/** Dimmer value of a light command. */
+fn dim_of(cmd: &LightCommand) -> u8 {
+    match *cmd {
+        LightCommand::CCT(dim, _, _) => dim,
+        LightCommand::HSI(_, _, dim) => dim,
+    }
+}
+
+/** Light command with a different dimmer value. */
+fn with_dim(cmd: &LightCommand, dim: f32) -> LightCommand {
+    let dim = dim.round().clamp(0.0, 100.0) as u8;
+    match *cmd {
+        LightCommand::CCT(_, ct, gm) => LightCommand::CCT(dim, ct, gm),
+        LightCommand::HSI(hue, sat, _) => LightCommand::HSI(hue, sat, dim),
+    }
+}
+
+impl Running {
+    fn new(address: u16, effect: Effect, now: Instant) -> Running {
+        Running {
+            effect,
+            start: now,
+            rng: Rng::new(0x9e3779b9 ^ address as u32),
+            level: 0.0,
+            target: 0.0,
+            next_target: now,
+            last_cmd: None,
+        }
+    }
+
+    /** Move the random level towards a new random target, `speed` times per second.
+     * `pick` gives the new target from a random value.
+     */
+    fn flicker(&mut self, now: Instant, pick: impl Fn(f32) -> f32) {
+        if now >= self.next_target {
+            self.target = pick(self.rng.random());
+            let period = 1.0 / (self.effect.speed * (0.5 + self.rng.random()));
+            self.next_target = now + Duration::from_secs_f32(period);
+        }
+        self.level += (self.target - self.level) * 0.5;
+    }
+
+    /** Command to send to the light at the given time, based on the light's own command. */
+    fn command_at(&mut self, base: Option<&LightCommand>, now: Instant) -> Option<LightCommand> {
+        let t = now.saturating_duration_since(self.start).as_secs_f32();
+        let phase = (t * self.effect.speed).fract();
+        let depth = self.effect.depth as f32 / 100.0;
+        // Without a known state, color effects run at full brightness. Dimmer
+        // effects are only started on lights with a known state.
+        let dim = base.map_or(100.0, |cmd| dim_of(cmd) as f32);
+
+        match self.effect.effect_type {
+            EffectType::Strobe => {
+                let level = if phase < 0.25 { 1.0 } else { 1.0 - depth };
+                base.map(|cmd| with_dim(cmd, dim * level))
+            }
+            EffectType::Pulse => {
+                let level = 1.0 - depth * (1.0 - (2.0 * PI * phase).cos()) / 2.0;
+                base.map(|cmd| with_dim(cmd, dim * level))
+            }
+            EffectType::Candle => {
+                self.flicker(now, |r| r * r);
+                let level = 1.0 - depth * self.level;
+                base.map(|cmd| with_dim(cmd, dim * level))
+            }
+            EffectType::Lightning => {
+                // Random flashes to full brightness, that decay quickly.
+                if now >= self.next_target {
+                    self.level = 1.0;
+                    let period = 1.0 / (self.effect.speed * (0.25 + 1.5 * self.rng.random()));
+                    self.next_target = now + Duration::from_secs_f32(period);
+                } else {
+                    self.level *= 0.6;
+                }
+                let dark = dim * (1.0 - depth);
+                base.map(|cmd| with_dim(cmd, dark + (100.0 - dark) * self.level))
+            }
+            EffectType::Police => {
+                // Red flash, then blue flash, each followed by a dark period.
+                let hue = if phase < 0.5 { 0 } else { 240 };
+                let flash = (phase * 2.0).fract() < 0.5;
+                let level = if flash { 1.0 } else { 1.0 - depth };
+                Some(LightCommand::HSI(hue, 100, (dim * level).round() as u8))
+            }
+            EffectType::Rainbow => {
+                let start_hue = match base {
+                    Some(LightCommand::HSI(hue, _, _)) => *hue as f32,
+                    _ => 0.0,
+                };
+                let hue = (start_hue + 360.0 * phase).round() as u16 % 360;
+                let sat = (100.0 * depth).round() as u8;
+                Some(LightCommand::HSI(hue, sat, dim.round() as u8))
+            }
+            EffectType::TV => {
+                // Cold, slightly colored light that changes brightness randomly.
+                self.flicker(now, |r| r);
+                let hue = 200 + (self.level * 40.0) as u16;
+                let sat = 10 + (self.level * 30.0) as u8;
+                let level = 1.0 - depth * self.level;
+                Some(LightCommand::HSI(hue, sat, (dim * level).round() as u8))
+            }
+        }
+    }
+}
+
+/** Task that runs effects on lights. Commands for lights without an effect are
+ * passed through to the lights thread, for lights with an effect they become the
+ * base of the effect.
+ */
+pub async fn effects_task(
+    interval: Duration,
+    mut rx: mpsc::Receiver<(u16, LightCommand)>,
+    mut effect_rx: mpsc::Receiver<EffectRequest>,
+    tx: mpsc::Sender<(u16, LightCommand)>,
+) {
+    info!("Effects thread running");
+    // Last command received for each light address.
+    let mut bases: HashMap<u16, LightCommand> = HashMap::new();
+    let mut effects: HashMap<u16, Running> = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        let mut out: Vec<(u16, LightCommand)> = Vec::new();
+        tokio::select! {
+            cmd = rx.recv() => {
+                let Some((address, cmd)) = cmd else {
+                    break;
+                };
+                bases.insert(address, cmd);
+                if !effects.contains_key(&address) {
+                    out.push((address, cmd));
+                }
+            }
+            req = effect_rx.recv() => {
+                let Some(req) = req else {
+                    break;
+                };
+                match req {
+                    EffectRequest::Start(address, effect, base) => {
+                        debug!("Start effect {:?} on address {}", effect, address);
+                        if let Some(base) = base {
+                            bases.entry(address).or_insert(base);
+                        }
+                        effects.insert(address, Running::new(address, effect, Instant::now()));
+                    }
+                    EffectRequest::Stop(address) => {
+                        debug!("Stop effect on address {}", address);
+                        // Restore the light to its own state.
+                        if effects.remove(&address).is_some()
+                            && let Some(base) = bases.get(&address)
+                        {
+                            out.push((address, *base));
+                        }
+                    }
+                }
+            }
+            _ = ticker.tick(), if !effects.is_empty() => {
+                let now = Instant::now();
+                for (address, running) in effects.iter_mut() {
+                    let cmd = running.command_at(bases.get(address), now);
+                    if cmd.is_some() && cmd != running.last_cmd {
+                        out.extend(cmd.map(|cmd| (*address, cmd)));
+                        running.last_cmd = cmd;
+                    }
+                }
+            }
+        }
+
+        for cmd in out {
+            if tx.send(cmd).await.is_err() {
+                error!("Lights thread is gone");
+                return;
+            }
+        }
+    }
+}
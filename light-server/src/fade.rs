@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use log::{debug, error, info};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use light_protocol::{Easing, State, Transition};
+
+use crate::{LightCommand, configuration, update_light};
+
+/** Request to change a light to a new state, optionally with a transition.
+ * Requests are sent to the fade thread in batches, which start at the same time.
+ */
+#[derive(Debug, Clone)]
+pub struct FadeRequest {
+    pub idx: usize,
+    pub from: State,
+    pub to: State,
+    pub transition: Option<Transition>,
+}
+
+/** Fade in progress on a light. */
+struct Fade {
+    from: State,
+    to: State,
+    start: Instant,
+    duration: Duration,
+    easing: Easing,
+    /// Last command sent for this fade, to avoid sending duplicates.
+    last_cmd: Option<LightCommand>,
+}
+
+impl Fade {
+    /** Position in fade (0.0..1.0) at the given time, before easing. */
+    fn position(&self, now: Instant) -> f32 {
+        let elapsed = now.saturating_duration_since(self.start);
+        (elapsed.as_secs_f32() / self.duration.as_secs_f32()).min(1.0)
+    }
+
+    /** Interpolated state at the given time. */
+    fn state_at(&self, now: Instant) -> State {
+        interpolate(&self.from, &self.to, ease(self.easing, self.position(now)))
+    }
+}
+
+/** Apply easing curve to a position 0.0..1.0. */
+fn ease(easing: Easing, t: f32) -> f32 {
+    match easing {
+        Easing::Linear => t,
+        Easing::EaseIn => t * t,
+        Easing::EaseOut => t * (2.0 - t),
+        Easing::EaseInOut => {
+            if t < 0.5 {
+                2.0 * t * t
+            } else {
+                -1.0 + (4.0 - 2.0 * t) * t
+            }
+        }
+    }
+}
+
+/** Linearly interpolate a value, if both ends are known. */
+fn lerp(from: Option<i32>, to: Option<i32>, t: f32) -> Option<i32> {
+    match (from, to) {
+        (Some(from), Some(to)) => Some(from + ((to - from) as f32 * t).round() as i32),
+        _ => to,
+    }
+}
+
+/** Interpolate hue the shortest way around the color circle. */
+fn lerp_hue(from: Option<u16>, to: Option<u16>, t: f32) -> Option<u16> {
+    match (from, to) {
+        (Some(from), Some(to)) => {
+            let diff = (to as i32 - from as i32 + 540) % 360 - 180;
+            let hue = from as i32 + (diff as f32 * t).round() as i32;
+            Some(hue.rem_euclid(360) as u16)
+        }
+        _ => to,
+    }
+}
+
+/** Interpolate between two states.
+ * If the mode changes, the new mode is used right away and only the dimmer is faded.
+ */
+fn interpolate(from: &State, to: &State, t: f32) -> State {
+    let dim = lerp(from.dim.map(i32::from), to.dim.map(i32::from), t).map(|v| v as u16);
+    if from.mode != to.mode {
+        return State { dim, ..to.clone() };
+    }
+    State {
+        mode: to.mode,
+        dim,
+        ct: lerp(from.ct.map(i32::from), to.ct.map(i32::from), t).map(|v| v as u16),
+        gm: lerp(from.gm.map(i32::from), to.gm.map(i32::from), t).map(|v| v as i16),
+        hue: lerp_hue(from.hue, to.hue, t),
+        sat: lerp(from.sat.map(i32::from), to.sat.map(i32::from), t).map(|v| v as u16),
+        ..State::default()
+    }
+}
+
+/** Task that receives light changes, and sends them to the lights thread,
+ * either right away or as a series of intermediate steps.
+ */
+pub async fn fade_task(
+    light_config: Vec<configuration::Light>,
+    interval: Duration,
+    mut rx: mpsc::Receiver<Vec<FadeRequest>>,
+    tx: mpsc::Sender<(u16, LightCommand)>,
+) {
+    info!("Fade thread running");
+    let mut fades: HashMap<usize, Fade> = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        let mut out: Vec<(u16, LightCommand)> = Vec::new();
+        tokio::select! {
+            reqs = rx.recv() => {
+                let Some(reqs) = reqs else {
+                    break;
+                };
+                let now = Instant::now();
+                for req in reqs {
+                    // Continue from the current position if the light was already fading.
+                    let from = match fades.remove(&req.idx) {
+                        Some(fade) => fade.state_at(now),
+                        None => req.from,
+                    };
+                    let light = &light_config[req.idx];
+                    match req.transition {
+                        Some(transition) if transition.duration > 0 => {
+                            debug!("Fade light {} over {}ms", req.idx, transition.duration);
+                            fades.insert(
+                                req.idx,
+                                Fade {
+                                    last_cmd: update_light(light, &from),
+                                    from,
+                                    to: req.to,
+                                    start: now,
+                                    duration: Duration::from_millis(transition.duration as u64),
+                                    easing: transition.easing,
+                                },
+                            );
+                        }
+                        _ => {
+                            out.extend(update_light(light, &req.to).map(|cmd| (light.address, cmd)));
+                        }
+                    }
+                }
+            }
+            _ = ticker.tick(), if !fades.is_empty() => {
+                let now = Instant::now();
+                for (idx, fade) in fades.iter_mut() {
+                    let light = &light_config[*idx];
+                    let cmd = update_light(light, &fade.state_at(now));
+                    if cmd.is_some() && cmd != fade.last_cmd {
+                        out.extend(cmd.map(|cmd| (light.address, cmd)));
+                        fade.last_cmd = cmd;
+                    }
+                }
+                fades.retain(|_, fade| fade.position(now) < 1.0);
+            }
+        }
+
+        for cmd in out {
+            if tx.send(cmd).await.is_err() {
+                error!("Lights thread is gone");
+                return;
+            }
+        }
+    }
+}
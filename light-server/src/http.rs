@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{self, Path};
+use axum::http::{StatusCode, header};
+use axum::response::{Html, IntoResponse};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+
+use light_protocol::{
+    DeliveryStats, ErrorCode, LightInfo, Response, ResponseType, State, Transition,
+};
+
+use crate::{
+    CommandError, Shared, apply_state, configuration, delete_scene, describe_response,
+    hello_response, light_config, recall_scene, save_scene,
+};
+
+/** Web control panel, embedded in the binary so that the server is self-contained. */
+const INDEX_HTML: &str = include_str!("../web/index.html");
+const APP_JS: &str = include_str!("../web/app.js");
+const STYLE_CSS: &str = include_str!("../web/style.css");
+
+/** Light description with its current state. */
+#[derive(Serialize, Debug)]
+struct LightView {
+    idx: u16,
+    #[serde(flatten)]
+    info: LightInfo,
+    state: State,
+}
+
+/** Change to a light: the values to change, and an optional transition. */
+#[derive(Deserialize, Debug)]
+struct SetRequest {
+    #[serde(flatten)]
+    state: State,
+    #[serde(default)]
+    transition: Option<Transition>,
+}
+
+/** Optional parameters of a scene recall. */
+#[derive(Deserialize, Debug, Default)]
+struct RecallRequest {
+    #[serde(default)]
+    transition: Option<Transition>,
+}
+
+impl IntoResponse for CommandError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self.code {
+            ErrorCode::ParseError => StatusCode::BAD_REQUEST,
+            ErrorCode::UnknownIdx
+            | ErrorCode::UnknownScene
+            | ErrorCode::UnknownCueList
+            | ErrorCode::UnknownCue
+            | ErrorCode::UnknownGroup => StatusCode::NOT_FOUND,
+            ErrorCode::InvalidRange | ErrorCode::ModeIncomplete | ErrorCode::UnsupportedMode => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let body = json!({ "error": self.message, "code": self.code });
+        (status, Json(body)).into_response()
+    }
+}
+
+/** Error for a request that could not be parsed. */
+fn parse_error(message: String) -> CommandError {
+    CommandError::new(ErrorCode::ParseError, message)
+}
+
+fn light_view(shared: &Shared, idx: u16) -> Result<LightView, CommandError> {
+    let light = light_config(shared, idx)?;
+    Ok(LightView {
+        idx,
+        info: light.info(idx as usize),
+        state: shared.light_states.lock().unwrap()[idx as usize].clone(),
+    })
+}
+
+async fn get_lights(extract::State(shared): extract::State<Arc<Shared>>) -> Json<Vec<LightView>> {
+    let lights = (0..shared.light_config.len() as u16)
+        .map(|idx| light_view(&shared, idx).unwrap())
+        .collect();
+    Json(lights)
+}
+
+async fn get_light(
+    extract::State(shared): extract::State<Arc<Shared>>,
+    path: Result<Path<u16>, PathRejection>,
+) -> Result<Json<LightView>, CommandError> {
+    let Path(idx) = path.map_err(|e| parse_error(e.body_text()))?;
+    Ok(Json(light_view(&shared, idx)?))
+}
+
+async fn patch_light(
+    extract::State(shared): extract::State<Arc<Shared>>,
+    path: Result<Path<u16>, PathRejection>,
+    body: Result<Json<SetRequest>, JsonRejection>,
+) -> Result<Json<LightView>, CommandError> {
+    let Path(idx) = path.map_err(|e| parse_error(e.body_text()))?;
+    let Json(req) = body.map_err(|e| parse_error(e.body_text()))?;
+    debug!("HTTP set light {}: {:?}", idx, req);
+    let fade_req = apply_state(&shared, None, idx, &req.state, req.transition)?;
+    shared.fade_tx.send(vec![fade_req]).await.unwrap();
+    Ok(Json(light_view(&shared, idx)?))
+}
+
+async fn get_scenes(extract::State(shared): extract::State<Arc<Shared>>) -> Json<Vec<String>> {
+    Json(shared.scenes.lock().unwrap().names())
+}
+
+async fn get_stats(
+    extract::State(shared): extract::State<Arc<Shared>>,
+) -> Json<Vec<DeliveryStats>> {
+    Json(shared.delivery_stats.lock().unwrap().clone())
+}
+
+async fn refresh(extract::State(shared): extract::State<Arc<Shared>>) -> StatusCode {
+    let _ = shared.refresh_tx.try_send(());
+    StatusCode::NO_CONTENT
+}
+
+async fn put_scene(
+    extract::State(shared): extract::State<Arc<Shared>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, CommandError> {
+    save_scene(&shared, &name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_scene_handler(
+    extract::State(shared): extract::State<Arc<Shared>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, CommandError> {
+    delete_scene(&shared, &name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn recall_scene_handler(
+    extract::State(shared): extract::State<Arc<Shared>>,
+    Path(name): Path<String>,
+    body: Bytes,
+) -> Result<StatusCode, CommandError> {
+    // The body is optional, so that a plain POST recalls the scene right away.
+    let req: RecallRequest = if body.is_empty() {
+        RecallRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| parse_error(e.to_string()))?
+    };
+    recall_scene(&shared, &name, req.transition).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn websocket(
+    extract::State(shared): extract::State<Arc<Shared>>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| websocket_task(shared, socket))
+}
+
+async fn send_response(socket: &mut WebSocket, response: &Response) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(response).unwrap();
+    socket.send(Message::Text(text.into())).await
+}
+
+/** Task that streams state changes to a WebSocket, as responses of the JSONL protocol.
+ */
+async fn websocket_task(shared: Arc<Shared>, mut socket: WebSocket) {
+    debug!("WebSocket connected");
+    // Subscribe before sending the initial state, so that no changes are missed.
+    let mut updates_rx = shared.updates.subscribe();
+
+    let state = Response {
+        state: Some(shared.light_states.lock().unwrap().clone()),
+        ..Response::new(ResponseType::State)
+    };
+    for response in [hello_response(&shared), describe_response(&shared), state] {
+        if send_response(&mut socket, &response).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let response = tokio::select! {
+            msg = socket.recv() => {
+                match msg {
+                    // Incoming messages are ignored, changes are made through the REST API.
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                }
+            }
+            update = updates_rx.recv() => {
+                match update {
+                    Ok(update) => Response {
+                        idx: Some(update.idx),
+                        light: Some(update.state),
+                        ..Response::new(ResponseType::Update)
+                    },
+                    Err(broadcast::error::RecvError::Lagged(_)) => Response {
+                        state: Some(shared.light_states.lock().unwrap().clone()),
+                        ..Response::new(ResponseType::State)
+                    },
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+        if send_response(&mut socket, &response).await.is_err() {
+            break;
+        }
+    }
+    debug!("WebSocket disconnected");
+}
+
+/** Task that serves the HTTP API: REST endpoints for lights and scenes,
+ * a WebSocket that streams state changes, and the web control panel.
+ */
+pub async fn http_task(shared: Arc<Shared>, config: configuration::Http) {
+    let listener = match TcpListener::bind(&config.bind_addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Error binding HTTP socket {}: {}", config.bind_addr, err);
+            return;
+        }
+    };
+    info!("Serving HTTP on {}", config.bind_addr);
+
+    let app = Router::new()
+        .route("/", get(|| async { Html(INDEX_HTML) }))
+        .route(
+            "/app.js",
+            get(|| async { ([(header::CONTENT_TYPE, "text/javascript")], APP_JS) }),
+        )
+        .route(
+            "/style.css",
+            get(|| async { ([(header::CONTENT_TYPE, "text/css")], STYLE_CSS) }),
+        )
+        .route("/lights", get(get_lights))
+        .route("/lights/{idx}", get(get_light).patch(patch_light))
+        .route("/scenes", get(get_scenes))
+        .route(
+            "/scenes/{name}",
+            put(put_scene).delete(delete_scene_handler),
+        )
+        .route("/scenes/{name}/recall", post(recall_scene_handler))
+        .route("/stats", get(get_stats))
+        .route("/refresh", post(refresh))
+        .route("/ws", get(websocket))
+        .with_state(shared);
+    if let Err(err) = axum::serve(listener, app).await {
+        error!("Error serving HTTP: {}", err);
+    }
+}
@@ -1,17 +1,34 @@
 use clap::Parser;
 use log::{debug, info, trace, warn};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 
-use light_protocol::{Command, ModeType, Response, ResponseType, State};
+use light_protocol::{
+    Command, CommandType, Cue, DeliveryStats, EffectType, ErrorCode, Hello, ModeType,
+    PROTOCOL_VERSION, Playback, Response, ResponseType, State, Transition, ValueRanges,
+};
 
+mod backend;
 mod configuration;
+mod cues;
+mod delivery;
+mod dmx;
+mod effects;
+mod fade;
+mod http;
+mod models;
+mod mqtt;
 mod nanlite;
+mod osc;
+mod persistence;
+mod subscriptions;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -22,9 +39,17 @@ struct Cli {
 }
 
 /** Update state record from another state record.
- * Data items that are the unset, will stay the same.
+ * Data items that are the unset, will stay the same. Relative changes are added up,
+ * unless the value itself is set, see `apply_deltas`.
  */
 fn update_state(dest_state: &mut State, src_state: &State) {
+    fn merge_delta(dest_delta: &mut Option<i32>, value_set: bool, src_delta: Option<i32>) {
+        if value_set {
+            *dest_delta = src_delta;
+        } else if let Some(delta) = src_delta {
+            *dest_delta = Some(dest_delta.unwrap_or(0).saturating_add(delta));
+        }
+    }
     if let Some(mode) = src_state.mode {
         dest_state.mode = Some(mode);
     }
@@ -43,23 +68,134 @@ fn update_state(dest_state: &mut State, src_state: &State) {
     if let Some(sat) = src_state.sat {
         dest_state.sat = Some(sat);
     }
+    merge_delta(
+        &mut dest_state.dim_delta,
+        src_state.dim.is_some(),
+        src_state.dim_delta,
+    );
+    merge_delta(
+        &mut dest_state.ct_delta,
+        src_state.ct.is_some(),
+        src_state.ct_delta,
+    );
+    merge_delta(
+        &mut dest_state.gm_delta,
+        src_state.gm.is_some(),
+        src_state.gm_delta,
+    );
+    merge_delta(
+        &mut dest_state.hue_delta,
+        src_state.hue.is_some(),
+        src_state.hue_delta,
+    );
+    merge_delta(
+        &mut dest_state.sat_delta,
+        src_state.sat.is_some(),
+        src_state.sat_delta,
+    );
+}
+
+/** Apply the relative changes in a state record to its values, and clear them.
+ * The results are clamped to their valid ranges for the light, except for hue,
+ * which wraps around.
+ */
+fn apply_deltas(
+    idx: u16,
+    light: &configuration::Light,
+    state: &mut State,
+) -> Result<(), CommandError> {
+    fn adjust<T: Copy + Into<i32>>(
+        idx: u16,
+        name: &str,
+        value: &mut Option<T>,
+        delta: Option<i32>,
+        resolve: impl Fn(i32) -> T,
+    ) -> Result<(), CommandError> {
+        match (*value, delta) {
+            (_, None) => Ok(()),
+            (Some(old), Some(delta)) => {
+                *value = Some(resolve(old.into().saturating_add(delta)));
+                Ok(())
+            }
+            (None, Some(_)) => Err(CommandError::new(
+                ErrorCode::ModeIncomplete,
+                format!("light {} has no {} value to change", idx, name),
+            )),
+        }
+    }
+    let ranges = ValueRanges::default();
+    let (ct_min, ct_max) = light.ct_range();
+    adjust(idx, "dim", &mut state.dim, state.dim_delta.take(), |v| {
+        v.clamp(ranges.dim.0.into(), ranges.dim.1.into()) as u16
+    })?;
+    adjust(idx, "ct", &mut state.ct, state.ct_delta.take(), |v| {
+        v.clamp(ct_min.into(), ct_max.into()) as u16
+    })?;
+    adjust(idx, "gm", &mut state.gm, state.gm_delta.take(), |v| {
+        v.clamp(ranges.gm.0.into(), ranges.gm.1.into()) as i16
+    })?;
+    adjust(idx, "hue", &mut state.hue, state.hue_delta.take(), |v| {
+        v.rem_euclid(ranges.hue.1.into()) as u16
+    })?;
+    adjust(idx, "sat", &mut state.sat, state.sat_delta.take(), |v| {
+        v.clamp(ranges.sat.0.into(), ranges.sat.1.into()) as u16
+    })?;
+    Ok(())
+}
+
+/** Error while handling a command, reported back to the client. */
+#[derive(Debug)]
+struct CommandError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl CommandError {
+    fn new(code: ErrorCode, message: impl Into<String>) -> CommandError {
+        CommandError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/** Check that the values in a state record are within their valid ranges
+ * for the light.
+ */
+fn validate_state(light: &configuration::Light, state: &State) -> Result<(), CommandError> {
+    fn check<T: PartialOrd + std::fmt::Display>(
+        name: &str,
+        value: Option<T>,
+        min: T,
+        max: T,
+    ) -> Result<(), CommandError> {
+        match value {
+            Some(value) if value < min || value > max => Err(CommandError::new(
+                ErrorCode::InvalidRange,
+                format!("{} {} out of range {}..{}", name, value, min, max),
+            )),
+            _ => Ok(()),
+        }
+    }
+    let ranges = ValueRanges::default();
+    check("dim", state.dim, ranges.dim.0, ranges.dim.1)?;
+    let (ct_min, ct_max) = light.ct_range();
+    check("ct", state.ct, ct_min, ct_max)?;
+    check("gm", state.gm, ranges.gm.0, ranges.gm.1)?;
+    check("hue", state.hue, ranges.hue.0, ranges.hue.1)?;
+    check("sat", state.sat, ranges.sat.0, ranges.sat.1)?;
+    Ok(())
 }
 
 /** Create light update command from light state (if complete) to light thread.
  * Validate and convert values to nanlite values.
  */
-fn update_light(state: &State) -> Option<LightCommand> {
+fn update_light(light: &configuration::Light, state: &State) -> Option<LightCommand> {
     match state.mode {
         Some(ModeType::CCT) => {
             if let (Some(dim), Some(ct), Some(gm)) = (state.dim, state.ct, state.gm) {
-                // ct is mapped from 2700K..7500K
-                let ct_val: u8 = if ct < 2700 {
-                    0
-                } else if ct > 7500 {
-                    100
-                } else {
-                    (((ct as u32) - 2700) * 100 / (7500 - 2700)) as u8
-                };
+                // ct is mapped from the light's range
+                let ct_val: u8 = light.ct_curve().map(ct, light.ct_range());
                 // gm is mapped from -100..100 to 0..100
                 let gm_val: u8 = if gm < -100 {
                     0
@@ -95,107 +231,771 @@ fn update_light(state: &State) -> Option<LightCommand> {
 }
 
 /** Command to lights thread. */
-#[derive(Debug, Copy, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum LightCommand {
     CCT(u8, u8, u8),
     HSI(u16, u8, u8),
 }
 
 /** Task that receives light commands, and dispatches them to the radio.
+ * Commands that are superseded before they could be sent are dropped, and no more
+ * than `max_send_rate` commands are sent per second.
+ * The last command sent to each light is sent again when requested through
+ * `refresh_rx`, and every `refresh_interval_ms` if the light wasn't sent anything
+ * in the meantime, for lights that were switched off or out of range. Before
+ * anything is sent, that is the command for the light's state in `initial_states`.
+ * The delivery statistics of each light are updated after every command.
  */
 async fn lights_task(
+    mut backend: Box<dyn backend::LightBackend>,
     config: &configuration::Hardware,
+    light_config: Vec<configuration::Light>,
+    stats: Arc<Mutex<Vec<DeliveryStats>>>,
+    initial_states: Vec<State>,
     mut rx: mpsc::Receiver<(u16, LightCommand)>,
+    mut refresh_rx: mpsc::Receiver<()>,
 ) {
     info!("Light thread running");
-    let mut rf24 = nanlite::rf24_init(config.device.clone(), config.nrf24_ce_gpio).unwrap();
-    while let Some((idx, cmd)) = rx.recv().await {
-        debug!("GOT = {:?}", (idx, cmd));
-        match cmd {
-            LightCommand::CCT(intensity, cct, gm) => {
-                nanlite::set_intensity_cct_gm(&mut rf24, idx, intensity, cct, gm).unwrap();
+    let send_interval = Duration::from_secs(1) / config.max_send_rate;
+    let mut pending = delivery::Pending::default();
+    let mut next_send = tokio::time::Instant::now();
+    // Last command sent to each light address, and when.
+    let now = tokio::time::Instant::now();
+    let mut last_sent: HashMap<u16, (LightCommand, tokio::time::Instant)> = light_config
+        .iter()
+        .zip(&initial_states)
+        .filter_map(|(light, state)| Some((light.address, (update_light(light, state)?, now))))
+        .collect();
+    let refresh_interval = config.refresh_interval_ms.map(Duration::from_millis);
+    let mut next_refresh = refresh_interval.map(|interval| tokio::time::Instant::now() + interval);
+    loop {
+        // Time the next command can be sent, if any is waiting.
+        let next_due = pending
+            .next_due(tokio::time::Instant::now())
+            .map(|due| due.max(next_send));
+        tokio::select! {
+            cmd = rx.recv() => {
+                let Some((address, cmd)) = cmd else {
+                    break;
+                };
+                debug!("GOT = {:?}", (address, cmd));
+                pending.push(address, cmd);
+            }
+            _ = tokio::time::sleep_until(next_due.unwrap_or(next_send)), if next_due.is_some() => {
+                let Some((address, cmd, retry)) = pending.pop(tokio::time::Instant::now()) else {
+                    continue;
+                };
+                let delivery =
+                    delivery::send(backend.as_mut(), config, &mut pending, address, cmd, retry);
+                let mut stats = stats.lock().unwrap();
+                for (light, stats) in light_config.iter().zip(stats.iter_mut()) {
+                    if light.address == address {
+                        delivery.record(stats);
+                    }
+                }
+                last_sent.insert(address, (cmd, tokio::time::Instant::now()));
+                next_send = tokio::time::Instant::now() + send_interval;
+            }
+            Some(()) = refresh_rx.recv() => {
+                debug!("Refreshing all lights");
+                for (address, (cmd, _)) in &last_sent {
+                    pending.push_if_idle(*address, *cmd);
+                }
+            }
+            _ = tokio::time::sleep_until(next_refresh.unwrap_or_else(tokio::time::Instant::now)), if next_refresh.is_some() => {
+                let now = tokio::time::Instant::now();
+                let interval = refresh_interval.unwrap();
+                for (address, (cmd, sent_at)) in &last_sent {
+                    if now.duration_since(*sent_at) >= interval {
+                        pending.push_if_idle(*address, *cmd);
+                    }
+                }
+                next_refresh = Some(now + interval);
+            }
+        }
+    }
+}
+
+/** Notification of a changed light state, broadcast to all connections. */
+#[derive(Debug, Clone)]
+struct StateUpdate {
+    /// Connection that caused the change, which doesn't need to be notified.
+    origin: Option<std::net::SocketAddr>,
+    idx: u16,
+    state: State,
+    /// State before the change.
+    previous: State,
+}
+
+/** Server state shared between connections. */
+struct Shared {
+    light_config: Vec<configuration::Light>,
+    light_states: Arc<Mutex<Vec<State>>>,
+    fade_tx: mpsc::Sender<Vec<fade::FadeRequest>>,
+    effect_tx: mpsc::Sender<effects::EffectRequest>,
+    updates: broadcast::Sender<StateUpdate>,
+    scenes: Mutex<persistence::NamedStore<Vec<State>>>,
+    cuelists: Mutex<persistence::NamedStore<Vec<Cue>>>,
+    playback_tx: mpsc::Sender<cues::PlaybackRequest>,
+    playback: watch::Receiver<Playback>,
+    delivery_stats: Arc<Mutex<Vec<DeliveryStats>>>,
+    refresh_tx: mpsc::Sender<()>,
+    /// Time between steps of fades and effects.
+    fade_interval: Duration,
+}
+
+/** Get the configuration of a light. */
+fn light_config(shared: &Shared, idx: u16) -> Result<&configuration::Light, CommandError> {
+    shared.light_config.get(idx as usize).ok_or_else(|| {
+        CommandError::new(
+            ErrorCode::UnknownIdx,
+            format!("unknown light index {}", idx),
+        )
+    })
+}
+
+/** Apply a state change to a light.
+ * The state is only changed if the result is a complete state for its mode.
+ * Returns the change to send to the fade thread.
+ */
+fn apply_state(
+    shared: &Shared,
+    origin: Option<std::net::SocketAddr>,
+    idx: u16,
+    state: &State,
+    transition: Option<Transition>,
+) -> Result<fade::FadeRequest, CommandError> {
+    let mut fade_reqs = apply_states(shared, origin, &[(idx, state.clone())], transition)?;
+    Ok(fade_reqs.remove(0))
+}
+
+/** Apply state changes to several lights at once.
+ * Either all changes are made, or none if any of them is invalid. The changes are
+ * made under one lock, so that no other change can come in between.
+ * Returns the changes to send to the fade thread, one per light.
+ */
+fn apply_states(
+    shared: &Shared,
+    origin: Option<std::net::SocketAddr>,
+    changes: &[(u16, State)],
+    transition: Option<Transition>,
+) -> Result<Vec<fade::FadeRequest>, CommandError> {
+    let mut light_states_mut = shared.light_states.lock().unwrap();
+    // New state of each changed light, in order of first change.
+    let mut new_states: Vec<(u16, State)> = vec![];
+    for (idx, state) in changes {
+        let light = light_config(shared, *idx)?;
+        validate_state(light, state)?;
+
+        let pos = match new_states.iter().position(|(i, _)| i == idx) {
+            Some(pos) => pos,
+            None => {
+                new_states.push((*idx, light_states_mut[*idx as usize].clone()));
+                new_states.len() - 1
+            }
+        };
+        let new_state = &mut new_states[pos].1;
+        update_state(new_state, state);
+        apply_deltas(*idx, light, new_state)?;
+        if let Some(mode) = new_state.mode
+            && !light.modes().contains(&mode)
+        {
+            return Err(CommandError::new(
+                ErrorCode::UnsupportedMode,
+                format!("light {} does not support mode {:?}", idx, mode),
+            ));
+        }
+        if update_light(light, new_state).is_none() {
+            return Err(CommandError::new(
+                ErrorCode::ModeIncomplete,
+                format!("state of light {} is missing values for its mode", idx),
+            ));
+        }
+    }
+
+    let mut fade_reqs = vec![];
+    for (idx, new_state) in new_states {
+        let old_state = light_states_mut[idx as usize].clone();
+        if old_state != new_state {
+            light_states_mut[idx as usize] = new_state.clone();
+            // Send while holding the lock, so that updates are ordered the same as the state changes.
+            // This fails only if there are no receivers, which is fine.
+            let _ = shared.updates.send(StateUpdate {
+                origin,
+                idx,
+                state: new_state.clone(),
+                previous: old_state.clone(),
+            });
+        }
+        fade_reqs.push(fade::FadeRequest {
+            idx: idx as usize,
+            from: old_state,
+            to: new_state,
+            transition,
+        });
+    }
+    Ok(fade_reqs)
+}
+
+/** Recall a scene, setting all lights to the stored states.
+ * All clients are notified of the changes.
+ */
+async fn recall_scene(
+    shared: &Shared,
+    name: &str,
+    transition: Option<Transition>,
+) -> Result<(), CommandError> {
+    let Some(states) = shared.scenes.lock().unwrap().get(name).cloned() else {
+        return Err(CommandError::new(
+            ErrorCode::UnknownScene,
+            format!("unknown scene {:?}", name),
+        ));
+    };
+    info!("Recalling scene {:?}", name);
+    // Notify the originating client too, as it doesn't know the scene contents.
+    let mut fade_reqs = vec![];
+    for (idx, state) in states.iter().enumerate().take(shared.light_config.len()) {
+        match apply_state(shared, None, idx as u16, state, transition) {
+            Ok(fade_req) => fade_reqs.push(fade_req),
+            Err(err) => debug!("Skipping light {} in scene: {:?}", idx, err),
+        }
+    }
+    // Send all changes at once, so that they are transmitted together.
+    shared.fade_tx.send(fade_reqs).await.unwrap();
+    Ok(())
+}
+
+/** Save the current state of all lights as a scene. */
+fn save_scene(shared: &Shared, name: &str) -> Result<(), CommandError> {
+    let states = shared.light_states.lock().unwrap().clone();
+    shared
+        .scenes
+        .lock()
+        .unwrap()
+        .insert(name, states)
+        .map_err(|e| CommandError::new(ErrorCode::InternalError, e.to_string()))?;
+    info!("Saved scene {:?}", name);
+    Ok(())
+}
+
+/** Delete a scene. */
+fn delete_scene(shared: &Shared, name: &str) -> Result<(), CommandError> {
+    let removed = shared
+        .scenes
+        .lock()
+        .unwrap()
+        .remove(name)
+        .map_err(|e| CommandError::new(ErrorCode::InternalError, e.to_string()))?;
+    if !removed {
+        return Err(CommandError::new(
+            ErrorCode::UnknownScene,
+            format!("unknown scene {:?}", name),
+        ));
+    }
+    info!("Deleted scene {:?}", name);
+    Ok(())
+}
+
+/** Get the scene or cue list name from a command. */
+fn scene_name(command: &Command) -> Result<&str, CommandError> {
+    command
+        .name
+        .as_deref()
+        .ok_or_else(|| CommandError::new(ErrorCode::ParseError, "missing name"))
+}
+
+/** Error for a cue list that doesn't exist. */
+fn unknown_cuelist(name: &str) -> CommandError {
+    CommandError::new(
+        ErrorCode::UnknownCueList,
+        format!("unknown cue list {:?}", name),
+    )
+}
+
+/** Check that an effect can run on a light. */
+fn validate_effect(
+    shared: &Shared,
+    light: &configuration::Light,
+    idx: u16,
+    effect: &light_protocol::Effect,
+    base: Option<&LightCommand>,
+) -> Result<(), CommandError> {
+    // Effects are evaluated once per fade interval, faster effects would alias.
+    let max_speed = (0.5 / shared.fade_interval.as_secs_f32()).min(50.0);
+    if !(effect.speed > 0.0 && effect.speed <= max_speed) {
+        return Err(CommandError::new(
+            ErrorCode::InvalidRange,
+            format!("speed {} out of range 0..{}", effect.speed, max_speed),
+        ));
+    }
+    if effect.depth > 100 {
+        return Err(CommandError::new(
+            ErrorCode::InvalidRange,
+            format!("depth {} out of range 0..100", effect.depth),
+        ));
+    }
+    let needs_hsi = matches!(
+        effect.effect_type,
+        EffectType::Police | EffectType::Rainbow | EffectType::TV
+    );
+    if needs_hsi && !light.modes().contains(&ModeType::HSI) {
+        return Err(CommandError::new(
+            ErrorCode::UnsupportedMode,
+            format!("light {} does not support mode {:?}", idx, ModeType::HSI),
+        ));
+    }
+    if !needs_hsi && base.is_none() {
+        return Err(CommandError::new(
+            ErrorCode::ModeIncomplete,
+            format!(
+                "light {} has no state to run {:?} on",
+                idx, effect.effect_type
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/** Get the light changes of a batch command: "state" for the lights in "group",
+ * followed by the changes in "lights".
+ */
+fn batch_changes(shared: &Shared, command: &Command) -> Result<Vec<(u16, State)>, CommandError> {
+    let mut changes = vec![];
+    if let Some(group) = &command.group {
+        changes.extend(
+            shared
+                .light_config
+                .iter()
+                .enumerate()
+                .filter(|(_, light)| light.groups.contains(group))
+                .map(|(idx, _)| (idx as u16, command.state.clone())),
+        );
+        if changes.is_empty() {
+            return Err(CommandError::new(
+                ErrorCode::UnknownGroup,
+                format!("no lights in group {:?}", group),
+            ));
+        }
+    }
+    for change in command.lights.iter().flatten() {
+        changes.push((change.idx, change.state.clone()));
+    }
+    if changes.is_empty() {
+        return Err(CommandError::new(
+            ErrorCode::ParseError,
+            "missing lights or group",
+        ));
+    }
+    Ok(changes)
+}
+
+/** Send a command to the playback thread, and wait for the result. */
+async fn playback_command(shared: &Shared, cmd: cues::PlaybackCommand) -> Result<(), CommandError> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    shared.playback_tx.send((cmd, reply_tx)).await.unwrap();
+    reply_rx.await.unwrap()
+}
+
+/** Handle a command from a client.
+ */
+async fn handle_command(
+    shared: &Shared,
+    peer: std::net::SocketAddr,
+    subscriptions: &mut subscriptions::Subscriptions,
+    command: &Command,
+) -> Result<Response, CommandError> {
+    match command.cmd {
+        CommandType::Set => {
+            let Some(idx) = command.idx else {
+                return Err(CommandError::new(ErrorCode::ParseError, "missing idx"));
+            };
+            let fade_req =
+                apply_state(shared, Some(peer), idx, &command.state, command.transition)?;
+            // Send change to fade thread.
+            debug!("Out: {:?}", fade_req);
+            shared.fade_tx.send(vec![fade_req]).await.unwrap();
+        }
+        CommandType::Batch => {
+            let changes = batch_changes(shared, command)?;
+            let fade_reqs = apply_states(shared, Some(peer), &changes, command.transition)?;
+            debug!("Out: {:?}", fade_reqs);
+            shared.fade_tx.send(fade_reqs).await.unwrap();
+        }
+        CommandType::Get => {
+            let light_states = shared.light_states.lock().unwrap();
+            return Ok(match command.idx {
+                Some(idx) => {
+                    light_config(shared, idx)?;
+                    Response {
+                        idx: Some(idx),
+                        light: Some(light_states[idx as usize].clone()),
+                        ..Response::new(ResponseType::State)
+                    }
+                }
+                None => Response {
+                    state: Some(light_states.clone()),
+                    ..Response::new(ResponseType::State)
+                },
+            });
+        }
+        CommandType::Subscribe => {
+            for idx in command.idxs.iter().flatten() {
+                light_config(shared, *idx)?;
+            }
+            subscriptions.subscribe(command.idxs.as_deref(), command.fields.as_deref());
+        }
+        CommandType::Unsubscribe => {
+            for idx in command.idxs.iter().flatten() {
+                light_config(shared, *idx)?;
             }
-            LightCommand::HSI(hue, sat, intensity) => {
-                nanlite::set_hue_sat_intensity(&mut rf24, idx, hue, sat, intensity).unwrap();
+            subscriptions.unsubscribe(command.idxs.as_deref());
+        }
+        CommandType::Refresh => {
+            // A refresh that is already queued covers this one.
+            let _ = shared.refresh_tx.try_send(());
+        }
+        CommandType::Stats => {
+            return Ok(Response {
+                stats: Some(shared.delivery_stats.lock().unwrap().clone()),
+                ..Response::new(ResponseType::Stats)
+            });
+        }
+        CommandType::SceneSave => {
+            let name = scene_name(command)?;
+            save_scene(shared, name)?;
+        }
+        CommandType::SceneRecall => {
+            let name = scene_name(command)?;
+            recall_scene(shared, name, command.transition).await?;
+        }
+        CommandType::SceneList => {
+            return Ok(Response {
+                scenes: Some(shared.scenes.lock().unwrap().names()),
+                ..Response::new(ResponseType::Scenes)
+            });
+        }
+        CommandType::SceneDelete => {
+            let name = scene_name(command)?;
+            delete_scene(shared, name)?;
+        }
+        CommandType::CueListSave => {
+            let name = scene_name(command)?;
+            let Some(cues) = command.cues.clone() else {
+                return Err(CommandError::new(ErrorCode::ParseError, "missing cues"));
+            };
+            shared
+                .cuelists
+                .lock()
+                .unwrap()
+                .insert(name, cues)
+                .map_err(|e| CommandError::new(ErrorCode::InternalError, e.to_string()))?;
+            info!("Saved cue list {:?}", name);
+        }
+        CommandType::CueListList => {
+            return Ok(Response {
+                cuelists: Some(shared.cuelists.lock().unwrap().names()),
+                ..Response::new(ResponseType::CueLists)
+            });
+        }
+        CommandType::CueListDelete => {
+            let name = scene_name(command)?;
+            let removed = shared
+                .cuelists
+                .lock()
+                .unwrap()
+                .remove(name)
+                .map_err(|e| CommandError::new(ErrorCode::InternalError, e.to_string()))?;
+            if !removed {
+                return Err(unknown_cuelist(name));
             }
+            info!("Deleted cue list {:?}", name);
         }
+        CommandType::CueLoad => {
+            let name = scene_name(command)?;
+            let Some(cues) = shared.cuelists.lock().unwrap().get(name).cloned() else {
+                return Err(unknown_cuelist(name));
+            };
+            playback_command(shared, cues::PlaybackCommand::Load(name.to_string(), cues)).await?;
+        }
+        CommandType::CueJump => {
+            let Some(cue) = command.cue else {
+                return Err(CommandError::new(ErrorCode::ParseError, "missing cue"));
+            };
+            playback_command(shared, cues::PlaybackCommand::Jump(cue)).await?;
+        }
+        CommandType::CueGo
+        | CommandType::CueBack
+        | CommandType::CuePause
+        | CommandType::CueResume
+        | CommandType::CueStop => {
+            let cmd = match command.cmd {
+                CommandType::CueGo => cues::PlaybackCommand::Go,
+                CommandType::CueBack => cues::PlaybackCommand::Back,
+                CommandType::CuePause => cues::PlaybackCommand::Pause,
+                CommandType::CueResume => cues::PlaybackCommand::Resume,
+                _ => cues::PlaybackCommand::Stop,
+            };
+            playback_command(shared, cmd).await?;
+        }
+        CommandType::EffectStart => {
+            let Some(idx) = command.idx else {
+                return Err(CommandError::new(ErrorCode::ParseError, "missing idx"));
+            };
+            let Some(effect) = command.effect else {
+                return Err(CommandError::new(ErrorCode::ParseError, "missing effect"));
+            };
+            let light = light_config(shared, idx)?;
+            // Effects without a color of their own need the state of the light.
+            let base = update_light(light, &shared.light_states.lock().unwrap()[idx as usize]);
+            validate_effect(shared, light, idx, &effect, base.as_ref())?;
+            info!("Starting effect {:?} on light {}", effect.effect_type, idx);
+            let req = effects::EffectRequest::Start(light.address, effect, base);
+            shared.effect_tx.send(req).await.unwrap();
+        }
+        CommandType::EffectStop => {
+            let Some(idx) = command.idx else {
+                return Err(CommandError::new(ErrorCode::ParseError, "missing idx"));
+            };
+            let light = light_config(shared, idx)?;
+            let req = effects::EffectRequest::Stop(light.address);
+            shared.effect_tx.send(req).await.unwrap();
+        }
+    }
+    Ok(Response::new(ResponseType::OK))
+}
+
+/** Get the id of a command that could not be parsed, if it is valid JSON with an id. */
+fn command_id(line: &str) -> Option<serde_json::Value> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    value.get("id").cloned()
+}
+
+/** Protocol features of this server, announced in "hello". */
+const CAPABILITIES: &[&str] = &[
+    "transitions",
+    "scenes",
+    "cuelists",
+    "effects",
+    "ids",
+    "batch",
+    "query",
+    "deltas",
+    "stats",
+    "refresh",
+];
+
+/** Hello message, describing the server to a new connection. */
+fn hello_response(shared: &Shared) -> Response {
+    let mut modes: Vec<ModeType> = vec![];
+    for mode in shared.light_config.iter().flat_map(|light| light.modes()) {
+        if !modes.contains(&mode) {
+            modes.push(mode);
+        }
+    }
+    Response {
+        hello: Some(Hello {
+            server: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            protocol: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+            num_lights: shared.light_config.len(),
+            modes,
+            ranges: ValueRanges::default(),
+        }),
+        ..Response::new(ResponseType::Hello)
     }
 }
 
+/** Description of all lights, for a new connection. */
+fn describe_response(shared: &Shared) -> Response {
+    Response {
+        lights: Some(
+            shared
+                .light_config
+                .iter()
+                .enumerate()
+                .map(|(idx, light)| light.info(idx))
+                .collect(),
+        ),
+        ..Response::new(ResponseType::Describe)
+    }
+}
+
+/** Serialize and write a response to a connection.
+ */
+async fn write_response(
+    writer: &mut (impl AsyncWriteExt + Unpin),
+    response: &Response,
+) -> std::io::Result<()> {
+    let response_string = serde_json::to_string(response).unwrap() + "\n";
+    writer.write_all(response_string.as_bytes()).await
+}
+
 /** Task that handles an incoming connection.
  */
 async fn connection_task(
-    light_config: &Vec<configuration::Light>,
-    light_states: Arc<Mutex<Vec<State>>>,
-    tx: mpsc::Sender<(u16, LightCommand)>,
+    shared: Arc<Shared>,
     mut stream: tokio::net::TcpStream,
     peer: std::net::SocketAddr,
 ) {
-    info!("Thread {} starting", peer.to_string());
+    info!("Thread {} starting", peer);
     let (reader, mut writer) = stream.split();
     let mut buf_reader = BufReader::new(reader);
 
+    // Subscribe before sending the initial state, so that no changes are missed.
+    let mut updates_rx = shared.updates.subscribe();
+    let mut playback_rx = shared.playback.clone();
+    let mut subscriptions = subscriptions::Subscriptions::all(shared.light_config.len());
+
+    // Introduce server to new connection
+    if let Err(e) = write_response(&mut writer, &hello_response(&shared)).await {
+        warn!("Error sending message: {}", e);
+        return;
+    }
+
+    // Describe lights to new connection
+    if let Err(e) = write_response(&mut writer, &describe_response(&shared)).await {
+        warn!("Error sending message: {}", e);
+        return;
+    }
+
     // Print initial state to new connection
-    let response: Response = Response {
-        response: ResponseType::State,
-        error: None,
-        state: Some(light_states.lock().unwrap().clone()),
+    // States of the lights as last sent to the connection, to catch up after lagging.
+    let mut known = shared.light_states.lock().unwrap().clone();
+    let response = Response {
+        state: Some(known.clone()),
+        ..Response::new(ResponseType::State)
     };
-    let response_string = serde_json::to_string(&response).unwrap() + "\n";
-    writer.write_all(response_string.as_bytes()).await.unwrap();
+    if let Err(e) = write_response(&mut writer, &response).await {
+        warn!("Error sending message: {}", e);
+        return;
+    }
+
+    // Print playback status to new connection
+    let response = Response {
+        playback: Some(playback_rx.borrow_and_update().clone()),
+        ..Response::new(ResponseType::Playback)
+    };
+    if let Err(e) = write_response(&mut writer, &response).await {
+        warn!("Error sending message: {}", e);
+        return;
+    }
 
     let mut buf = vec![];
-    loop {
-        match buf_reader.read_until(b'\n', &mut buf).await {
-            Ok(n) => {
-                if n == 0 {
-                    debug!("EOF received");
-                    break;
-                }
-                let buf_string = String::from_utf8_lossy(&buf);
-                trace!("Received line: {:?}", buf_string);
-                let command: Command = serde_json::from_str(&buf_string).unwrap();
-                debug!("Received message: {:?}", command);
-                let update_command = {
-                    let mut light_states_mut = light_states.lock().unwrap();
-                    update_state(&mut light_states_mut[command.idx as usize], &command.state);
-                    update_light(&light_states_mut[command.idx as usize])
-                };
+    'connection: loop {
+        let responses = tokio::select! {
+            res = buf_reader.read_until(b'\n', &mut buf) => {
+                match res {
+                    Ok(0) => {
+                        debug!("EOF received");
+                        break;
+                    }
+                    Ok(_) => {
+                        let buf_string = String::from_utf8_lossy(&buf);
+                        trace!("Received line: {:?}", buf_string);
+                        let command = serde_json::from_str::<Command>(&buf_string);
+                        let (id, result) = match command {
+                            Ok(command) => {
+                                debug!("Received message: {:?}", command);
+                                (command.id.clone(), handle_command(&shared, peer, &mut subscriptions, &command).await)
+                            }
+                            Err(e) => (
+                                command_id(&buf_string),
+                                Err(CommandError::new(ErrorCode::ParseError, e.to_string())),
+                            ),
+                        };
+                        buf.clear();
 
-                // Send command to light thread.
-                debug!("Out: {:?}", update_command);
-                if let Some(light_cmd) = update_command {
-                    tx.send((light_config[command.idx as usize].address, light_cmd))
-                        .await
-                        .unwrap();
+                        let response = match result {
+                            Ok(response) => response,
+                            Err(err) => {
+                                debug!("Command error: {:?}", err);
+                                Response {
+                                    error: Some(err.message),
+                                    code: Some(err.code),
+                                    ..Response::new(ResponseType::Err)
+                                }
+                            }
+                        };
+                        vec![Response { id, ..response }]
+                    }
+                    Err(e) => {
+                        warn!("Error receiving message: {}", e);
+                        break;
+                    }
                 }
-
-                // TODO: error handling for invalid input
-                let response: Response = Response {
-                    response: ResponseType::OK,
-                    error: None,
-                    state: None,
-                };
-
-                // Write response.
-                let response_string = serde_json::to_string(&response).unwrap() + "\n";
-                writer.write_all(response_string.as_bytes()).await.unwrap();
-
-                buf.clear();
             }
-            Err(e) => {
-                warn!("Error receiving message: {}", e);
-                break;
+            update = updates_rx.recv() => {
+                match update {
+                    Ok(update) if update.origin == Some(peer) => {
+                        // The connection made the change itself.
+                        known[update.idx as usize] = update.state;
+                        continue;
+                    }
+                    Ok(update) if !subscriptions.wants(&update) => continue,
+                    Ok(update) => {
+                        known[update.idx as usize] = update.state.clone();
+                        vec![Response {
+                            idx: Some(update.idx),
+                            light: Some(update.state),
+                            ..Response::new(ResponseType::Update)
+                        }]
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        // Missed some updates, send the subscribed changes since the
+                        // last state the connection knows of instead.
+                        debug!("Lagged {} updates", n);
+                        let light_states = shared.light_states.lock().unwrap().clone();
+                        let mut responses = vec![];
+                        for (idx, state) in light_states.into_iter().enumerate() {
+                            let update = StateUpdate {
+                                origin: None,
+                                idx: idx as u16,
+                                state,
+                                previous: known[idx].clone(),
+                            };
+                            if subscriptions.wants(&update) {
+                                known[idx] = update.state.clone();
+                                responses.push(Response {
+                                    idx: Some(update.idx),
+                                    light: Some(update.state),
+                                    ..Response::new(ResponseType::Update)
+                                });
+                            }
+                        }
+                        responses
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            changed = playback_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                vec![Response {
+                    playback: Some(playback_rx.borrow_and_update().clone()),
+                    ..Response::new(ResponseType::Playback)
+                }]
+            }
+        };
+
+        // Write responses.
+        for response in &responses {
+            if let Err(e) = write_response(&mut writer, response).await {
+                warn!("Error sending message: {}", e);
+                break 'connection;
             }
         }
     }
 
-    info!("Thread {} finishing", peer.to_string());
+    info!("Thread {} finishing", peer);
 }
 
 fn read_config(config_file: &Path) -> Result<configuration::Configuration, Box<dyn Error>> {
     let config_data = fs::read_to_string(config_file)?;
-    Ok(serde_json::from_str(&config_data)?)
+    let config: configuration::Configuration = serde_json::from_str(&config_data)?;
+    config.validate()?;
+    Ok(config)
 }
 
 #[tokio::main]
@@ -210,40 +1010,275 @@ async fn main() {
     }
     let config = config.unwrap();
 
+    // Scenes are stored next to the configuration file by default.
+    let scenes_file = config
+        .scenes_file
+        .clone()
+        .unwrap_or_else(|| cli.config_file.with_file_name("scenes.json"));
+    let scenes = match persistence::NamedStore::load(&scenes_file) {
+        Ok(scenes) => scenes,
+        Err(err) => {
+            eprintln!("Error loading scenes {}: {}", scenes_file.display(), err);
+            return;
+        }
+    };
+
+    // Cue lists are stored next to the configuration file by default.
+    let cues_file = config
+        .cues_file
+        .clone()
+        .unwrap_or_else(|| cli.config_file.with_file_name("cues.json"));
+    let cuelists = match persistence::NamedStore::load(&cues_file) {
+        Ok(cuelists) => cuelists,
+        Err(err) => {
+            eprintln!("Error loading cue lists {}: {}", cues_file.display(), err);
+            return;
+        }
+    };
+
+    // Open the radio before accepting clients, as nothing works without it.
+    let backend = match backend::open(&config.hardware) {
+        Ok(backend) => backend,
+        Err(err) => {
+            eprintln!(
+                "Error opening {:?} backend: {}",
+                config.hardware.backend, err
+            );
+            return;
+        }
+    };
+
     let addr = config.network.bind_addr.clone();
     let socket = TcpListener::bind(&addr).await.unwrap();
 
     info!("Listening on {}", addr);
 
-    // Initial light states (unknown).
+    // Initial light states (restored from state file, or unknown).
     let num_lights = config.lights.len();
-    let initial_states: Vec<State> = vec![
-        State {
-            mode: None,
-            dim: None,
-            ct: None,
-            gm: None,
-            hue: None,
-            sat: None
-        };
-        num_lights
-    ];
+    let initial_states: Vec<State> = match &config.persistence {
+        Some(persistence) if persistence.state_file.exists() => {
+            match persistence::load_states(&persistence.state_file, num_lights) {
+                Ok(states) => {
+                    info!("Restored state from {}", persistence.state_file.display());
+                    states
+                }
+                Err(err) => {
+                    warn!(
+                        "Error loading state file {}: {}",
+                        persistence.state_file.display(),
+                        err
+                    );
+                    vec![State::default(); num_lights]
+                }
+            }
+        }
+        _ => vec![State::default(); num_lights],
+    };
     let light_states = Arc::new(Mutex::new(initial_states));
 
     // Make channel for communicating with lights thread.
     let (tx, rx) = mpsc::channel::<(u16, LightCommand)>(32);
 
+    // Make channel for communicating with fade thread.
+    let (fade_tx, fade_rx) = mpsc::channel::<Vec<fade::FadeRequest>>(32);
+
+    // Make channel for broadcasting state changes to all connections.
+    let (updates, _) = broadcast::channel::<StateUpdate>(64);
+
+    // Make channels for communicating with effects thread.
+    let (effect_lights_tx, effect_lights_rx) = mpsc::channel::<(u16, LightCommand)>(32);
+    let (effect_tx, effect_rx) = mpsc::channel::<effects::EffectRequest>(32);
+
+    // Make channels for communicating with playback thread.
+    let (playback_tx, playback_rx) = mpsc::channel::<cues::PlaybackRequest>(32);
+    let (playback_status, playback) = watch::channel(Playback::default());
+
     // Spawn lights thread.
     let hardware_config = config.hardware.clone();
-    tokio::spawn(async move { lights_task(&hardware_config, rx).await });
+    let light_config = config.lights.clone();
+    let delivery_stats = Arc::new(Mutex::new(vec![DeliveryStats::default(); num_lights]));
+    let lights_stats = delivery_stats.clone();
+    let (refresh_tx, refresh_rx) = mpsc::channel::<()>(1);
+    let initial_states = light_states.lock().unwrap().clone();
+    tokio::spawn(async move {
+        lights_task(
+            backend,
+            &hardware_config,
+            light_config,
+            lights_stats,
+            initial_states,
+            rx,
+            refresh_rx,
+        )
+        .await
+    });
 
-    while let Ok((stream, peer)) = socket.accept().await {
+    // Spawn fade thread.
+    let light_config = config.lights.clone();
+    let fade_interval = Duration::from_millis(config.hardware.fade_interval_ms);
+    tokio::spawn(async move {
+        fade::fade_task(light_config, fade_interval, fade_rx, effect_lights_tx).await
+    });
+
+    // Spawn effects thread.
+    let lights_tx = tx.clone();
+    tokio::spawn(async move {
+        effects::effects_task(fade_interval, effect_lights_rx, effect_rx, lights_tx).await
+    });
+
+    if let Some(persistence) = &config.persistence {
+        // Re-transmit restored state to the lights.
+        if persistence.retransmit {
+            let states = light_states.lock().unwrap().clone();
+            for (light, state) in config.lights.iter().zip(states.iter()) {
+                if let Some(light_cmd) = update_light(light, state) {
+                    tx.send((light.address, light_cmd)).await.unwrap();
+                }
+            }
+        }
+
+        // Spawn state writer.
+        let path = persistence.state_file.clone();
         let light_states = light_states.clone();
-        let tx = tx.clone();
-        let light_config = config.lights.clone();
-        info!("Incoming connection from: {}", peer.to_string());
+        let updates_rx = updates.subscribe();
+        tokio::spawn(async move { persistence::writer_task(path, light_states, updates_rx).await });
+    }
+
+    let shared = Arc::new(Shared {
+        light_config: config.lights.clone(),
+        light_states,
+        fade_tx,
+        effect_tx,
+        updates,
+        scenes: Mutex::new(scenes),
+        cuelists: Mutex::new(cuelists),
+        playback_tx,
+        playback,
+        delivery_stats,
+        refresh_tx,
+        fade_interval,
+    });
+
+    // Spawn playback thread.
+    let playback_shared = shared.clone();
+    tokio::spawn(async move {
+        cues::playback_task(playback_shared, playback_rx, playback_status).await
+    });
+
+    // Spawn DMX receiver.
+    if let Some(dmx_config) = config.dmx.clone() {
+        let dmx_shared = shared.clone();
+        tokio::spawn(async move { dmx::dmx_task(dmx_shared, dmx_config).await });
+    }
+
+    // Spawn OSC receiver.
+    if let Some(osc_config) = config.osc.clone() {
+        let osc_shared = shared.clone();
+        tokio::spawn(async move { osc::osc_task(osc_shared, osc_config).await });
+    }
+
+    // Spawn HTTP API.
+    if let Some(http_config) = config.http.clone() {
+        let http_shared = shared.clone();
+        tokio::spawn(async move { http::http_task(http_shared, http_config).await });
+    }
+
+    // Spawn MQTT bridge.
+    if let Some(mqtt_config) = config.mqtt.clone() {
+        let mqtt_shared = shared.clone();
+        tokio::spawn(async move { mqtt::mqtt_task(mqtt_shared, mqtt_config).await });
+    }
+
+    while let Ok((stream, peer)) = socket.accept().await {
+        let shared = shared.clone();
+        info!("Incoming connection from: {}", peer);
         tokio::spawn(async move {
-            connection_task(&light_config, light_states, tx, stream, peer).await;
+            connection_task(shared, stream, peer).await;
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light() -> configuration::Light {
+        serde_json::from_str(r#"{"address": 1, "ct_range": [3000, 6000]}"#).unwrap()
+    }
+
+    fn applied(state: State) -> Result<State, CommandError> {
+        let mut state = state;
+        apply_deltas(0, &light(), &mut state)?;
+        Ok(state)
+    }
+
+    #[test]
+    fn ct_delta_clamped_to_light_range() {
+        let state = |ct, ct_delta| State {
+            ct: Some(ct),
+            ct_delta: Some(ct_delta),
+            ..State::default()
+        };
+        assert_eq!(applied(state(5800, 500)).unwrap().ct, Some(6000));
+        assert_eq!(applied(state(3100, -500)).unwrap().ct, Some(3000));
+        assert_eq!(applied(state(4000, 100)).unwrap().ct, Some(4100));
+    }
+
+    #[test]
+    fn hue_delta_wraps_around() {
+        let state = |hue, hue_delta| State {
+            hue: Some(hue),
+            hue_delta: Some(hue_delta),
+            ..State::default()
+        };
+        assert_eq!(applied(state(350, 20)).unwrap().hue, Some(10));
+        assert_eq!(applied(state(10, -30)).unwrap().hue, Some(340));
+        assert_eq!(applied(state(0, -720)).unwrap().hue, Some(0));
+    }
+
+    #[test]
+    fn deltas_add_up() {
+        let mut state = State {
+            dim: Some(50),
+            ..State::default()
+        };
+        let delta = State {
+            dim_delta: Some(10),
+            ..State::default()
+        };
+        update_state(&mut state, &delta);
+        update_state(&mut state, &delta);
+        let state = applied(state).unwrap();
+        assert_eq!(state.dim, Some(70));
+        assert_eq!(state.dim_delta, None);
+    }
+
+    #[test]
+    fn absolute_value_applied_before_delta() {
+        let mut state = State {
+            dim: Some(50),
+            dim_delta: Some(30),
+            ..State::default()
+        };
+        // Setting the value discards earlier deltas, but not the one sent with it.
+        update_state(
+            &mut state,
+            &State {
+                dim: Some(20),
+                dim_delta: Some(5),
+                ..State::default()
+            },
+        );
+        assert_eq!(applied(state).unwrap().dim, Some(25));
+    }
+
+    #[test]
+    fn delta_without_value_is_incomplete() {
+        let err = applied(State {
+            sat_delta: Some(10),
+            ..State::default()
+        })
+        .unwrap_err();
+        assert_eq!(err.code, ErrorCode::ModeIncomplete);
+    }
+}
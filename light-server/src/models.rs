@@ -0,0 +1,66 @@
+use light_protocol::{ModeType, Model};
+use serde::{Deserialize, Serialize};
+
+/** Curve used to map a color temperature in Kelvin to the light's 0..100 scale. */
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum CtCurve {
+    /// Linear in Kelvin.
+    #[serde(rename = "linear")]
+    Linear,
+    /// Linear in mired (micro reciprocal degrees).
+    #[serde(rename = "mired")]
+    Mired,
+}
+
+impl CtCurve {
+    /** Map a color temperature in Kelvin to 0..100, clamping to the range. */
+    pub fn map(&self, ct: u16, (ct_min, ct_max): (u16, u16)) -> u8 {
+        if ct_max <= ct_min {
+            return 0;
+        }
+        let ct = ct.clamp(ct_min, ct_max);
+        match self {
+            CtCurve::Linear => (((ct - ct_min) as u32) * 100 / ((ct_max - ct_min) as u32)) as u8,
+            CtCurve::Mired => {
+                let mired = |k: u16| 1_000_000.0 / k as f32;
+                let pos = (mired(ct_min) - mired(ct)) / (mired(ct_min) - mired(ct_max));
+                (pos * 100.0).round().clamp(0.0, 100.0) as u8
+            }
+        }
+    }
+}
+
+/** Capabilities of a light model. */
+#[derive(Debug, Copy, Clone)]
+pub struct ModelInfo {
+    /// Supported modes.
+    pub modes: &'static [ModeType],
+    /// Color temperature range in Kelvin.
+    pub ct_range: (u16, u16),
+    /// Mapping of color temperature to the light's scale.
+    pub ct_curve: CtCurve,
+}
+
+/** Capabilities assumed for lights without a configured model. */
+pub const DEFAULT_MODEL_INFO: ModelInfo = ModelInfo {
+    modes: &[ModeType::CCT, ModeType::HSI],
+    ct_range: (2700, 7500),
+    ct_curve: CtCurve::Linear,
+};
+
+/** Look up capabilities of a light model. */
+pub fn model_info(model: Option<Model>) -> ModelInfo {
+    match model {
+        Some(Model::PavoTubeII6C) => ModelInfo {
+            modes: &[ModeType::CCT, ModeType::HSI],
+            ct_range: (2700, 7500),
+            ct_curve: CtCurve::Linear,
+        },
+        Some(Model::FC60B) | Some(Model::FC120B) => ModelInfo {
+            modes: &[ModeType::CCT],
+            ct_range: (2700, 6500),
+            ct_curve: CtCurve::Linear,
+        },
+        None => DEFAULT_MODEL_INFO,
+    }
+}
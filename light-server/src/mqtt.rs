@@ -0,0 +1,309 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{broadcast, mpsc};
+
+use light_protocol::{ErrorCode, ModeType, State, Transition};
+
+use crate::{CommandError, Shared, apply_state, configuration};
+
+/** Command from Home Assistant, in the JSON schema of the MQTT light. */
+#[derive(Deserialize, Debug)]
+struct HaCommand {
+    state: Option<String>,
+    brightness: Option<u16>,
+    /// Color temperature in Kelvin.
+    color_temp: Option<u16>,
+    color: Option<HsColor>,
+    /// Transition in seconds.
+    transition: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct HsColor {
+    h: f32,
+    s: f32,
+}
+
+/** State for Home Assistant, in the JSON schema of the MQTT light. */
+#[derive(Serialize, Debug)]
+struct LightState {
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_mode: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_temp: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<HsColor>,
+}
+
+/** Topics of the bridge. */
+struct Topics<'a> {
+    config: &'a configuration::Mqtt,
+}
+
+impl Topics<'_> {
+    fn availability(&self) -> String {
+        format!("{}/status", self.config.base_topic)
+    }
+
+    fn state(&self, idx: usize) -> String {
+        format!("{}/light/{}/state", self.config.base_topic, idx)
+    }
+
+    fn command(&self, idx: usize) -> String {
+        format!("{}/light/{}/set", self.config.base_topic, idx)
+    }
+
+    fn discovery(&self, idx: usize) -> String {
+        format!(
+            "{}/light/{}/light_{}/config",
+            self.config.discovery_prefix, self.config.client_id, idx
+        )
+    }
+
+    /** Parse the light index from a command topic. */
+    fn command_idx(&self, topic: &str) -> Option<usize> {
+        topic
+            .strip_prefix(&format!("{}/light/", self.config.base_topic))?
+            .strip_suffix("/set")?
+            .parse()
+            .ok()
+    }
+}
+
+/** Home Assistant discovery config of a light. */
+fn discovery_config(topics: &Topics, light: &configuration::Light, idx: usize) -> String {
+    let info = light.info(idx);
+    let color_modes: Vec<&str> = info
+        .modes
+        .iter()
+        .map(|mode| match mode {
+            ModeType::CCT => "color_temp",
+            ModeType::HSI => "hs",
+        })
+        .collect();
+    let unique_id = format!("{}_light_{}", topics.config.client_id, idx);
+    json!({
+        "name": null,
+        "unique_id": unique_id,
+        "schema": "json",
+        "command_topic": topics.command(idx),
+        "state_topic": topics.state(idx),
+        "availability_topic": topics.availability(),
+        "brightness": true,
+        "brightness_scale": 100,
+        "supported_color_modes": color_modes,
+        "color_temp_kelvin": true,
+        "min_kelvin": info.ct_range.0,
+        "max_kelvin": info.ct_range.1,
+        "device": {
+            "identifiers": [unique_id],
+            "name": info.name,
+            "manufacturer": "Nanlite",
+            "model": info.model,
+        },
+    })
+    .to_string()
+}
+
+/** Home Assistant state of a light. */
+fn light_state(state: &State) -> String {
+    let dim = state.dim.unwrap_or(0);
+    let (color_mode, color_temp, color) = match state.mode {
+        Some(ModeType::CCT) => (Some("color_temp"), state.ct, None),
+        Some(ModeType::HSI) => (
+            Some("hs"),
+            None,
+            Some(HsColor {
+                h: state.hue.unwrap_or(0) as f32,
+                s: state.sat.unwrap_or(0) as f32,
+            }),
+        ),
+        None => (None, None, None),
+    };
+    let ha_state = LightState {
+        state: if dim > 0 { "ON" } else { "OFF" },
+        brightness: state.dim.filter(|dim| *dim > 0),
+        color_mode,
+        color_temp,
+        color,
+    };
+    serde_json::to_string(&ha_state).unwrap()
+}
+
+/** Convert a command from Home Assistant to a state change.
+ * Values that Home Assistant doesn't know about are filled in, so that the result
+ * is complete for its mode.
+ */
+fn command_state(current: &State, last_dim: u16, cmd: &HaCommand) -> Result<State, CommandError> {
+    let mut state = State::default();
+    if let Some(ct) = cmd.color_temp {
+        state.mode = Some(ModeType::CCT);
+        state.ct = Some(ct);
+        state.gm = Some(current.gm.unwrap_or(0));
+    }
+    if let Some(color) = &cmd.color {
+        state.mode = Some(ModeType::HSI);
+        state.hue = Some(color.h.round() as u16 % 360);
+        state.sat = Some(color.s.round() as u16);
+    }
+    state.dim = match cmd.state.as_deref() {
+        Some("OFF") => Some(0),
+        // Turning on without brightness restores the last brightness.
+        Some("ON") => Some(cmd.brightness.unwrap_or(match current.dim {
+            Some(dim) if dim > 0 => dim,
+            _ => last_dim,
+        })),
+        Some(other) => {
+            return Err(CommandError::new(
+                ErrorCode::ParseError,
+                format!("unknown state {:?}", other),
+            ));
+        }
+        None => cmd.brightness,
+    };
+    // Turning on a light that has never been set, defaults to a neutral white.
+    if current.mode.is_none() && state.mode.is_none() {
+        state.mode = Some(ModeType::CCT);
+        state.ct = Some(current.ct.unwrap_or(5600));
+        state.gm = Some(current.gm.unwrap_or(0));
+    }
+    Ok(state)
+}
+
+/** Task that polls the MQTT connection, and forwards incoming packets to the bridge. */
+async fn poll_task(mut eventloop: EventLoop, tx: mpsc::Sender<Event>) {
+    loop {
+        match eventloop.poll().await {
+            // Only forward what the bridge needs, so that acknowledgements of its own
+            // publishes can't fill up the channel while it is publishing.
+            Ok(event @ Event::Incoming(Packet::ConnAck(_) | Packet::Publish(_))) => {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(err) => {
+                warn!("MQTT connection error: {}", err);
+                // The event loop reconnects on the next poll.
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+/** Task that bridges the lights to Home Assistant over MQTT.
+ */
+pub async fn mqtt_task(shared: Arc<Shared>, config: configuration::Mqtt) {
+    let topics = Topics { config: &config };
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        topics.availability(),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    let (client, eventloop) = AsyncClient::new(options, 64);
+    let (event_tx, mut event_rx) = mpsc::channel(64);
+    tokio::spawn(async move { poll_task(eventloop, event_tx).await });
+    info!("MQTT bridge connecting to {}:{}", config.host, config.port);
+
+    let mut updates_rx = shared.updates.subscribe();
+    // Last brightness of each light before it was turned off.
+    let mut last_dim: Vec<u16> = vec![100; shared.light_config.len()];
+    loop {
+        // States to publish.
+        let mut publish: Vec<usize> = vec![];
+        tokio::select! {
+            event = event_rx.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                match event {
+                    Event::Incoming(Packet::ConnAck(_)) => {
+                        info!("MQTT connected");
+                        for (idx, light) in shared.light_config.iter().enumerate() {
+                            let config = discovery_config(&topics, light, idx);
+                            let _ = client.publish(topics.discovery(idx), QoS::AtLeastOnce, true, config).await;
+                            let _ = client.subscribe(topics.command(idx), QoS::AtLeastOnce).await;
+                        }
+                        let _ = client.publish(topics.availability(), QoS::AtLeastOnce, true, "online").await;
+                        publish.extend(0..shared.light_config.len());
+                    }
+                    Event::Incoming(Packet::Publish(msg)) => {
+                        let Some(idx) = topics
+                            .command_idx(&msg.topic)
+                            .filter(|idx| *idx < shared.light_config.len())
+                        else {
+                            continue;
+                        };
+                        debug!("MQTT command for light {}: {:?}", idx, msg.payload);
+                        let result = match serde_json::from_slice::<HaCommand>(&msg.payload) {
+                            Ok(cmd) => handle_command(&shared, idx, last_dim[idx], &cmd).await,
+                            Err(e) => Err(CommandError::new(ErrorCode::ParseError, e.to_string())),
+                        };
+                        if let Err(err) = result {
+                            warn!("MQTT command for light {}: {}", idx, err.message);
+                            // Make sure Home Assistant shows the actual state.
+                            publish.push(idx);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            update = updates_rx.recv() => {
+                match update {
+                    Ok(update) => publish.push(update.idx as usize),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        publish.extend(0..shared.light_config.len());
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+
+        for idx in publish {
+            let state = shared.light_states.lock().unwrap()[idx].clone();
+            if let Some(dim) = state.dim.filter(|dim| *dim > 0) {
+                last_dim[idx] = dim;
+            }
+            let _ = client
+                .publish(
+                    topics.state(idx),
+                    QoS::AtLeastOnce,
+                    true,
+                    light_state(&state),
+                )
+                .await;
+        }
+    }
+}
+
+/** Apply a command from Home Assistant to a light. */
+async fn handle_command(
+    shared: &Shared,
+    idx: usize,
+    last_dim: u16,
+    cmd: &HaCommand,
+) -> Result<(), CommandError> {
+    let current = shared.light_states.lock().unwrap()[idx].clone();
+    let state = command_state(&current, last_dim, cmd)?;
+    let transition = cmd.transition.map(|secs| Transition {
+        duration: (secs * 1000.0) as u32,
+        easing: Default::default(),
+    });
+    let fade_req = apply_state(shared, None, idx as u16, &state, transition)?;
+    shared.fade_tx.send(vec![fade_req]).await.unwrap();
+    Ok(())
+}
@@ -1,3 +1,16 @@
+/* Nanlite Radio v1 protocol.
+ *
+ * Radio settings: channel 0x73, 1 Mbps, 16-bit CRC, auto-ack, 5-byte addresses and fixed
+ * 4-byte payloads. Lights listen on address 00 00 00 <addr hi> <addr lo>.
+ *
+ * Payloads:
+ *   CCT: [intensity, cct, gm, check]        all values 0..100, check = intensity + cct
+ *   HSI: [0xf0 | hue >> 8, intensity, hue & 0xff, sat]
+ *
+ * The v2 protocol used by newer firmware has different framing, which has not been
+ * reverse engineered yet, so it is not supported. Lights have to be set to v1.
+ */
+
 use std::path::Path;
 
 use linux_embedded_hal::{
@@ -11,11 +24,15 @@ use rf24::{
         RF24,
         prelude::{
             EsbAutoAck, EsbChannel, EsbCrcLength, EsbDataRate, EsbInit, EsbPaLevel,
-            EsbPayloadLength, EsbPipe, EsbRadio, RadioErrorType,
+            EsbPayloadLength, EsbPipe, RadioErrorType,
         },
     },
 };
 
+use crate::backend::{BackendError, LightBackend};
+
+pub type RF24Radio = RF24<SpidevDevice, CdevPin, Delay>;
+
 /*
 fn main() {
     let nrf24_ce_gpio = 25;
@@ -35,32 +52,40 @@ fn main() {
 pub fn rf24_init(
     spi_dev: impl AsRef<Path>,
     ce_gpio_offset: u32,
-) -> Result<
-    RF24<SpidevDevice, CdevPin, Delay>,
-    <RF24<SpidevDevice, CdevPin, Delay> as RadioErrorType>::Error,
-> {
-    let mut spi = Spidev::open(spi_dev).unwrap();
+) -> Result<RF24Radio, BackendError> {
+    let spi_dev = spi_dev.as_ref();
+    let mut spi = Spidev::open(spi_dev)
+        .map_err(|e| format!("Error opening SPI device {}: {}", spi_dev.display(), e))?;
     spi.configure(&SpidevOptions {
         bits_per_word: None,
         max_speed_hz: Some(8_000_000),
         lsb_first: None,
         spi_mode: Some(SpiModeFlags::SPI_MODE_0),
     })
-    .unwrap();
+    .map_err(|e| format!("Error configuring SPI device {}: {}", spi_dev.display(), e))?;
     let spi_device = SpidevDevice(spi);
 
-    let mut chip = Chip::new("/dev/gpiochip0").unwrap();
-    let ce_line = chip.get_line(ce_gpio_offset).unwrap();
+    let mut chip =
+        Chip::new("/dev/gpiochip0").map_err(|e| format!("Error opening GPIO chip: {}", e))?;
+    let ce_line = chip
+        .get_line(ce_gpio_offset)
+        .map_err(|e| format!("Error getting GPIO {}: {}", ce_gpio_offset, e))?;
     let ce_pin = CdevPin::new(
         ce_line
             .request(LineRequestFlags::OUTPUT, 0, "rust-nanlite-nrf24")
-            .unwrap(),
+            .map_err(|e| format!("Error requesting GPIO {}: {}", ce_gpio_offset, e))?,
     )
-    .unwrap();
+    .map_err(|e| format!("Error setting up GPIO {}: {:?}", ce_gpio_offset, e))?;
 
     let mut rf24 = RF24::new(ce_pin, spi_device, Delay);
+    configure(&mut rf24).map_err(|e| format!("Error initializing nRF24: {:?}", e))?;
 
-    rf24.init().unwrap();
+    Ok(rf24)
+}
+
+/** Initialize the radio with the settings of the v1 protocol. */
+fn configure(rf24: &mut RF24Radio) -> Result<(), <RF24Radio as RadioErrorType>::Error> {
+    rf24.init()?;
 
     rf24.set_crc_length(CrcLength::Bit16)?;
     rf24.set_channel(0x73)?;
@@ -72,7 +97,7 @@ pub fn rf24_init(
     rf24.set_data_rate(DataRate::Mbps1)?;
     rf24.set_address_length(5)?;
 
-    Ok(rf24)
+    Ok(())
 }
 
 /*
@@ -100,43 +125,43 @@ pub fn set_intensity_and_cct<Radio: EsbRadio>(
 }
 */
 
-pub fn set_hue_sat_intensity<Radio: EsbRadio>(
-    rf24: &mut Radio,
+/** Radio address for a light with the given address. */
+fn tx_address(addr: u16) -> [u8; 5] {
+    let addr_bytes = addr.to_be_bytes();
+    [0x00, 0x00, 0x00, addr_bytes[0], addr_bytes[1]]
+}
+
+/** Set a light to HSI mode. Returns whether the packet was acknowledged. */
+pub fn set_hue_sat_intensity<Backend: LightBackend + ?Sized>(
+    backend: &mut Backend,
     addr: u16,
     hue: u16,
     sat: u8,
     intensity: u8,
-) -> Result<(), Radio::Error> {
-    let addr_bytes = addr.to_be_bytes();
-    rf24.as_tx(Some(&[0x00, 0x00, 0x00, addr_bytes[0], addr_bytes[1]]))?;
-
+) -> Result<bool, BackendError> {
     let hue = hue.min(360);
     let sat = sat.min(100);
     let intensity = intensity.min(100);
 
-    rf24.send(
+    backend.transmit(
+        &tx_address(addr),
         &[
             0xf0 | ((hue >> 8) as u8),
             intensity,
             (hue & 0xff) as u8,
             sat,
         ],
-        false,
-    )?;
-
-    Ok(())
+    )
 }
 
-pub fn set_intensity_cct_gm<Radio: EsbRadio>(
-    rf24: &mut Radio,
+/** Set a light to CCT mode. Returns whether the packet was acknowledged. */
+pub fn set_intensity_cct_gm<Backend: LightBackend + ?Sized>(
+    backend: &mut Backend,
     addr: u16,
     intensity: u8,
     cct: u8,
     gm: u8,
-) -> Result<(), Radio::Error> {
-    let addr_bytes = addr.to_be_bytes();
-    rf24.as_tx(Some(&[0x00, 0x00, 0x00, addr_bytes[0], addr_bytes[1]]))?;
-
+) -> Result<bool, BackendError> {
     let intensity = intensity.min(100);
     let cct = cct.min(100);
     let gm = gm.min(100);
@@ -151,7 +176,5 @@ pub fn set_intensity_cct_gm<Radio: EsbRadio>(
         (cct, check)
     };
 
-    rf24.send(&[intensity, cct, gm, check], false)?;
-
-    Ok(())
+    backend.transmit(&tx_address(addr), &[intensity, cct, gm, check])
 }
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use log::{debug, error, info, warn};
+use tokio::net::UdpSocket;
+
+use light_protocol::{ErrorCode, ModeType, State};
+
+use crate::{
+    CommandError, Shared, apply_state, configuration, cues, playback_command, recall_scene,
+    update_state,
+};
+
+/** Argument of an OSC message. */
+#[derive(Debug, Clone, PartialEq)]
+enum Arg {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl Arg {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Arg::Int(v) => Some(*v as f64),
+            Arg::Float(v) => Some(*v),
+            Arg::Bool(v) => Some(if *v { 1.0 } else { 0.0 }),
+            Arg::Str(_) => None,
+        }
+    }
+}
+
+/** OSC message. */
+#[derive(Debug, Clone)]
+struct Message {
+    address: String,
+    args: Vec<Arg>,
+}
+
+/** Reader for the OSC binary format, where everything is aligned to 4 bytes. */
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let rest = self.data.get(self.pos..)?;
+        let len = rest.iter().position(|&b| b == 0)?;
+        // Strings are terminated by at least one zero, and padded to 4 bytes.
+        let bytes = self.bytes((len + 4) & !3)?;
+        Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+}
+
+/** Parse an OSC packet (message or bundle) into messages. */
+fn parse_packet(data: &[u8], messages: &mut Vec<Message>) -> Option<()> {
+    let mut reader = Reader { data, pos: 0 };
+    let address = reader.string()?;
+    if address == "#bundle" {
+        // Time tag is ignored, bundles are executed right away.
+        reader.u64()?;
+        while reader.pos < data.len() {
+            let len = reader.u32()? as usize;
+            parse_packet(reader.bytes(len)?, messages)?;
+        }
+        return Some(());
+    }
+    // Very old senders leave out the type tags.
+    let tags = if reader.pos < data.len() {
+        reader.string()?
+    } else {
+        String::new()
+    };
+    let mut args = vec![];
+    for tag in tags.chars().skip_while(|&c| c == ',') {
+        let arg = match tag {
+            'i' => Arg::Int(reader.u32()? as i32 as i64),
+            'h' => Arg::Int(reader.u64()? as i64),
+            'f' => Arg::Float(f32::from_bits(reader.u32()?) as f64),
+            'd' => Arg::Float(f64::from_bits(reader.u64()?)),
+            's' | 'S' => Arg::Str(reader.string()?),
+            'T' => Arg::Bool(true),
+            'F' => Arg::Bool(false),
+            // Nil and impulse have no data.
+            'N' | 'I' => continue,
+            _ => {
+                debug!("Unsupported OSC type tag {:?}", tag);
+                return None;
+            }
+        };
+        args.push(arg);
+    }
+    messages.push(Message { address, args });
+    Some(())
+}
+
+/** Get a numeric argument of a message. */
+fn number(message: &Message, n: usize) -> Result<f64, CommandError> {
+    message
+        .args
+        .get(n)
+        .and_then(Arg::as_f64)
+        .ok_or_else(|| CommandError::new(ErrorCode::ParseError, "missing numeric argument"))
+}
+
+/** Whether a trigger message should fire. Buttons send 1 when pressed and 0 when released. */
+fn triggered(message: &Message) -> bool {
+    message.args.first().and_then(Arg::as_f64) != Some(0.0)
+}
+
+/** Light state change from a message to a light address. */
+fn light_state(field: &str, message: &Message) -> Result<State, CommandError> {
+    // Values too large for their type saturate, and are then checked like any
+    // other value. Negative values would saturate to 0, so they are rejected.
+    let value = |n| number(message, n).map(|v| v.round());
+    let unsigned = |n| {
+        let v = value(n)?;
+        if v < 0.0 {
+            return Err(CommandError::new(
+                ErrorCode::InvalidRange,
+                format!("negative value for {}", field),
+            ));
+        }
+        Ok(v as u16)
+    };
+    let state = match field {
+        "mode" => {
+            let mode = match message.args.first() {
+                Some(Arg::Str(mode)) if mode == "cct" => ModeType::CCT,
+                Some(Arg::Str(mode)) if mode == "hsi" => ModeType::HSI,
+                _ => {
+                    return Err(CommandError::new(
+                        ErrorCode::ParseError,
+                        "mode must be \"cct\" or \"hsi\"",
+                    ));
+                }
+            };
+            State {
+                mode: Some(mode),
+                ..State::default()
+            }
+        }
+        "dim" => State {
+            dim: Some(unsigned(0)?),
+            ..State::default()
+        },
+        "ct" => State {
+            ct: Some(unsigned(0)?),
+            ..State::default()
+        },
+        "gm" => State {
+            gm: Some(value(0)? as i16),
+            ..State::default()
+        },
+        "hue" => State {
+            hue: Some(unsigned(0)?),
+            ..State::default()
+        },
+        "sat" => State {
+            sat: Some(unsigned(0)?),
+            ..State::default()
+        },
+        "dim_delta" => State {
+            dim_delta: Some(value(0)? as i32),
+            ..State::default()
+        },
+        "ct_delta" => State {
+            ct_delta: Some(value(0)? as i32),
+            ..State::default()
+        },
+        "gm_delta" => State {
+            gm_delta: Some(value(0)? as i32),
+            ..State::default()
+        },
+        "hue_delta" => State {
+            hue_delta: Some(value(0)? as i32),
+            ..State::default()
+        },
+        "sat_delta" => State {
+            sat_delta: Some(value(0)? as i32),
+            ..State::default()
+        },
+        "cct" => State {
+            mode: Some(ModeType::CCT),
+            dim: Some(unsigned(0)?),
+            ct: Some(unsigned(1)?),
+            gm: Some(value(2)? as i16),
+            ..State::default()
+        },
+        "hsi" => State {
+            mode: Some(ModeType::HSI),
+            hue: Some(unsigned(0)?),
+            sat: Some(unsigned(1)?),
+            dim: Some(unsigned(2)?),
+            ..State::default()
+        },
+        _ => {
+            return Err(CommandError::new(
+                ErrorCode::ParseError,
+                format!("unknown field {:?}", field),
+            ));
+        }
+    };
+    Ok(state)
+}
+
+/** Handle an OSC message. Light changes are collected in `light_changes`, so that
+ * all changes to a light in a bundle are applied at once.
+ */
+async fn handle_message(
+    shared: &Shared,
+    message: &Message,
+    light_changes: &mut BTreeMap<u16, State>,
+) -> Result<(), CommandError> {
+    let parts: Vec<&str> = message.address.split('/').skip(1).collect();
+    match parts.as_slice() {
+        ["light", idx, field] => {
+            let idx: u16 = idx.parse().map_err(|_| {
+                CommandError::new(
+                    ErrorCode::UnknownIdx,
+                    format!("invalid light index {}", idx),
+                )
+            })?;
+            let state = light_state(field, message)?;
+            update_state(light_changes.entry(idx).or_default(), &state);
+        }
+        ["scene", name] => {
+            if triggered(message) {
+                recall_scene(shared, name, None).await?;
+            }
+        }
+        ["cue", action] => {
+            let cmd = match *action {
+                "go" => cues::PlaybackCommand::Go,
+                "back" => cues::PlaybackCommand::Back,
+                "pause" => cues::PlaybackCommand::Pause,
+                "resume" => cues::PlaybackCommand::Resume,
+                "stop" => cues::PlaybackCommand::Stop,
+                _ => {
+                    return Err(CommandError::new(
+                        ErrorCode::ParseError,
+                        format!("unknown cue action {:?}", action),
+                    ));
+                }
+            };
+            if triggered(message) {
+                playback_command(shared, cmd).await?;
+            }
+        }
+        _ => {
+            return Err(CommandError::new(
+                ErrorCode::ParseError,
+                format!("unknown address {:?}", message.address),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/** Task that receives OSC messages over UDP, and executes them.
+ */
+pub async fn osc_task(shared: Arc<Shared>, config: configuration::Osc) {
+    let socket = match UdpSocket::bind(&config.bind_addr).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("Error binding OSC socket {}: {}", config.bind_addr, err);
+            return;
+        }
+    };
+    info!("Receiving OSC on {}", config.bind_addr);
+
+    let mut buf = [0u8; 65536];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(res) => res,
+            Err(err) => {
+                warn!("Error receiving OSC: {}", err);
+                continue;
+            }
+        };
+        let mut messages = vec![];
+        if parse_packet(&buf[..len], &mut messages).is_none() {
+            warn!("Invalid OSC packet from {}", peer);
+            continue;
+        }
+        let mut light_changes = BTreeMap::new();
+        for message in messages {
+            debug!("OSC from {}: {:?}", peer, message);
+            if let Err(err) = handle_message(&shared, &message, &mut light_changes).await {
+                warn!("OSC {}: {}", message.address, err.message);
+            }
+        }
+        let mut fade_reqs = vec![];
+        for (idx, state) in light_changes {
+            match apply_state(&shared, None, idx, &state, None) {
+                Ok(fade_req) => fade_reqs.push(fade_req),
+                Err(err) => warn!("OSC light {}: {}", idx, err.message),
+            }
+        }
+        if !fade_reqs.is_empty() {
+            shared.fade_tx.send(fade_reqs).await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize((s.len() + 4) & !3, 0);
+        bytes
+    }
+
+    fn message(address: &str, tags: &str, args: &[u8]) -> Vec<u8> {
+        let mut packet = string(address);
+        packet.extend(string(tags));
+        packet.extend_from_slice(args);
+        packet
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = string("#bundle");
+        packet.extend_from_slice(&1u64.to_be_bytes());
+        for element in elements {
+            packet.extend_from_slice(&(element.len() as u32).to_be_bytes());
+            packet.extend_from_slice(element);
+        }
+        packet
+    }
+
+    fn parse(data: &[u8]) -> Option<Vec<Message>> {
+        let mut messages = vec![];
+        parse_packet(data, &mut messages)?;
+        Some(messages)
+    }
+
+    #[test]
+    fn message_with_args() {
+        let mut args = (-5i32).to_be_bytes().to_vec();
+        args.extend_from_slice(&0.5f32.to_bits().to_be_bytes());
+        args.extend(string("hsi"));
+        let messages = parse(&message("/light/0/dim", ",ifsT", &args)).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].address, "/light/0/dim");
+        assert_eq!(
+            messages[0].args,
+            vec![
+                Arg::Int(-5),
+                Arg::Float(0.5),
+                Arg::Str("hsi".into()),
+                Arg::Bool(true)
+            ]
+        );
+    }
+
+    #[test]
+    fn message_without_type_tags() {
+        let messages = parse(&string("/cue/go")).unwrap();
+        assert_eq!(messages[0].address, "/cue/go");
+        assert!(messages[0].args.is_empty());
+    }
+
+    #[test]
+    fn truncated_message() {
+        let packet = message("/light/0/cct", ",iii", &[0; 12]);
+        // Cutting off at the end of the address leaves a message without type tags.
+        for len in (0..packet.len()).filter(|&len| len != 16) {
+            assert!(parse(&packet[..len]).is_none(), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn unsupported_type_tag() {
+        assert!(parse(&message("/light/0/dim", ",b", &[0; 4])).is_none());
+    }
+
+    #[test]
+    fn nested_bundles() {
+        let inner = bundle(&[
+            message("/light/0/dim", ",i", &50i32.to_be_bytes()),
+            message("/light/1/dim", ",i", &60i32.to_be_bytes()),
+        ]);
+        let outer = bundle(&[inner, message("/cue/go", ",", &[])]);
+        let messages = parse(&outer).unwrap();
+        let addresses: Vec<&str> = messages.iter().map(|m| m.address.as_str()).collect();
+        assert_eq!(addresses, ["/light/0/dim", "/light/1/dim", "/cue/go"]);
+    }
+
+    #[test]
+    fn bundle_with_bad_length() {
+        let mut packet = bundle(&[message("/cue/go", ",", &[])]);
+        packet[16..20].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(parse(&packet).is_none());
+    }
+
+    #[test]
+    fn truncated_bundle() {
+        let packet = bundle(&[message("/cue/go", ",", &[])]);
+        for len in 0..packet.len() {
+            // A bundle that ends after the time tag is empty, but valid.
+            if len != 16 {
+                assert!(parse(&packet[..len]).is_none(), "length {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn negative_values_rejected() {
+        let msg = |args| Message {
+            address: String::new(),
+            args,
+        };
+        for field in ["dim", "ct", "hue", "sat"] {
+            let err = light_state(field, &msg(vec![Arg::Int(-1)])).unwrap_err();
+            assert_eq!(err.code, ErrorCode::InvalidRange, "{}", field);
+        }
+        let err = light_state(
+            "cct",
+            &msg(vec![Arg::Int(50), Arg::Int(-3200), Arg::Int(0)]),
+        )
+        .unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidRange);
+        let state = light_state("gm", &msg(vec![Arg::Int(-50)])).unwrap();
+        assert_eq!(state.gm, Some(-50));
+        let state = light_state("dim_delta", &msg(vec![Arg::Float(-10.0)])).unwrap();
+        assert_eq!(state.dim_delta, Some(-10));
+    }
+}
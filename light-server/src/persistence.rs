@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::sync::broadcast;
+
+use light_protocol::State;
+
+use crate::StateUpdate;
+
+/** Time to wait after a change before writing the state file, so that a burst
+ * of changes results in a single write.
+ */
+const WRITE_DELAY: Duration = Duration::from_secs(1);
+
+/** Load light states from the state file.
+ * The result is resized to the number of configured lights.
+ */
+pub fn load_states(path: &Path, num_lights: usize) -> Result<Vec<State>, Box<dyn Error>> {
+    let data = fs::read_to_string(path)?;
+    let mut states: Vec<State> = serde_json::from_str(&data)?;
+    if states.len() != num_lights {
+        warn!(
+            "State file has {} lights, but {} are configured",
+            states.len(),
+            num_lights
+        );
+        states.resize(num_lights, State::default());
+    }
+    Ok(states)
+}
+
+/** Atomically write data to a file.
+ * The data is written to a temporary file first, then renamed over the old one.
+ * Both are synced to disk, so that the file is complete after a power loss.
+ */
+pub fn write_atomic(path: &Path, data: &str) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)?;
+
+    // Sync the directory, to make the rename itself durable.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/** Named items, such as scenes or cue lists, stored in a file. */
+pub struct NamedStore<T> {
+    path: PathBuf,
+    items: BTreeMap<String, T>,
+}
+
+impl<T: Serialize + DeserializeOwned> NamedStore<T> {
+    /** Load items from the given file. A file that doesn't exist yet means no items. */
+    pub fn load(path: &Path) -> Result<NamedStore<T>, Box<dyn Error>> {
+        let items = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(NamedStore {
+            path: path.to_path_buf(),
+            items,
+        })
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let data = serde_json::to_string_pretty(&self.items)? + "\n";
+        write_atomic(&self.path, &data)?;
+        Ok(())
+    }
+
+    /** Names of all items, in sorted order. */
+    pub fn names(&self) -> Vec<String> {
+        self.items.keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.items.get(name)
+    }
+
+    /** Store an item, replacing any item with the same name. */
+    pub fn insert(&mut self, name: &str, item: T) -> Result<(), Box<dyn Error>> {
+        self.items.insert(name.to_string(), item);
+        self.save()
+    }
+
+    /** Remove an item. Returns whether the item existed. */
+    pub fn remove(&mut self, name: &str) -> Result<bool, Box<dyn Error>> {
+        if self.items.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+}
+
+/** Write light states to the state file. */
+fn save_states(path: &Path, states: &[State]) -> Result<(), Box<dyn Error>> {
+    let data = serde_json::to_string_pretty(states)? + "\n";
+    write_atomic(path, &data)?;
+    Ok(())
+}
+
+/** Task that writes the light states to the state file after changes.
+ */
+pub async fn writer_task(
+    path: PathBuf,
+    light_states: Arc<Mutex<Vec<State>>>,
+    mut updates_rx: broadcast::Receiver<StateUpdate>,
+) {
+    info!("State writer running, state file {}", path.display());
+    loop {
+        // Wait for a change.
+        match updates_rx.recv().await {
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+        // Collect changes for a while, then write everything at once.
+        tokio::time::sleep(WRITE_DELAY).await;
+        updates_rx = updates_rx.resubscribe();
+
+        let states = light_states.lock().unwrap().clone();
+        debug!("Writing state file");
+        if let Err(err) = save_states(&path, &states) {
+            warn!("Error writing state file {}: {}", path.display(), err);
+        }
+    }
+}
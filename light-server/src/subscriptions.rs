@@ -0,0 +1,55 @@
+use light_protocol::StateField;
+
+use crate::StateUpdate;
+
+/** Change notifications that a connection subscribed to. */
+pub struct Subscriptions {
+    /// Subscribed fields of each light, or `None` if not subscribed to the light.
+    lights: Vec<Option<Vec<StateField>>>,
+}
+
+impl Subscriptions {
+    /** Subscriptions to all fields of all lights, as connections start out with. */
+    pub fn all(num_lights: usize) -> Subscriptions {
+        Subscriptions {
+            lights: vec![Some(StateField::ALL.to_vec()); num_lights],
+        }
+    }
+
+    /** Subscribe to the given lights, or all lights, replacing their subscribed fields.
+     * The light indices must be valid.
+     */
+    pub fn subscribe(&mut self, idxs: Option<&[u16]>, fields: Option<&[StateField]>) {
+        let fields = fields.unwrap_or(&StateField::ALL).to_vec();
+        match idxs {
+            Some(idxs) => {
+                for idx in idxs {
+                    self.lights[*idx as usize] = Some(fields.clone());
+                }
+            }
+            None => self.lights.fill(Some(fields)),
+        }
+    }
+
+    /** Unsubscribe from the given lights, or all lights. The light indices must be valid. */
+    pub fn unsubscribe(&mut self, idxs: Option<&[u16]>) {
+        match idxs {
+            Some(idxs) => {
+                for idx in idxs {
+                    self.lights[*idx as usize] = None;
+                }
+            }
+            None => self.lights.fill(None),
+        }
+    }
+
+    /** Whether the connection should be notified of a change. */
+    pub fn wants(&self, update: &StateUpdate) -> bool {
+        match self.lights.get(update.idx as usize) {
+            Some(Some(fields)) => fields
+                .iter()
+                .any(|field| field.differs(&update.previous, &update.state)),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,333 @@
+"use strict";
+
+// Color preview, the same as light-tui's color::compute_preview.
+
+// Conversion to u8 like Rust's `as u8`: truncate and saturate.
+function u8(v) {
+  return Number.isNaN(v) ? 0 : Math.min(255, Math.max(0, Math.trunc(v)));
+}
+
+function applyDimming([r, g, b], dim) {
+  const factor = Math.pow(dim / 100, 0.25);
+  return [u8(r * factor), u8(g * factor), u8(b * factor)];
+}
+
+function applyGm(rgb, gm) {
+  if (gm === 0) {
+    return rgb;
+  }
+  const target = gm < 0 ? [255, 0, 255] : [0, 255, 0];
+  const strength = (Math.abs(gm) / 100) * 0.2;
+  return rgb.map((c, i) => u8(c * (1 - strength) + target[i] * strength));
+}
+
+function hsiToRgb(h, s, i) {
+  const c = s / 100;
+  const x = c * (1 - Math.abs(((h / 60) % 2) - 1));
+  const m = 1 - c;
+  let rgb;
+  if (h < 60) {
+    rgb = [c, x, 0];
+  } else if (h < 120) {
+    rgb = [x, c, 0];
+  } else if (h < 180) {
+    rgb = [0, c, x];
+  } else if (h < 240) {
+    rgb = [0, x, c];
+  } else if (h < 300) {
+    rgb = [x, 0, c];
+  } else {
+    rgb = [c, 0, x];
+  }
+  return applyDimming(rgb.map((v) => u8((v + m) * 255)), i);
+}
+
+function kelvinToRgb(k) {
+  const temp = Math.min(40000, Math.max(1000, k)) / 100;
+  const r = temp <= 66 ? 255 : 329.698727446 * Math.pow(temp - 60, -0.1332047592);
+  const g = temp <= 66
+    ? 99.4708025861 * Math.log(temp) - 161.1195681661
+    : 288.1221695283 * Math.pow(temp - 60, -0.0755148492);
+  let b;
+  if (temp >= 66) {
+    b = 255;
+  } else if (temp <= 19) {
+    b = 0;
+  } else {
+    b = 138.5177312231 * Math.log(temp - 10) - 305.0447927307;
+  }
+  return [r, g, b].map((v) => u8(Math.min(255, Math.max(0, v))));
+}
+
+function computePreview(state) {
+  const mode = state.mode ?? "cct";
+  const dim = state.dim ?? 0;
+  let rgb;
+  if (mode === "cct") {
+    rgb = applyDimming(applyGm(kelvinToRgb(state.ct ?? 2700), state.gm ?? 0), dim);
+  } else {
+    rgb = hsiToRgb(state.hue ?? 0, state.sat ?? 0, dim);
+  }
+  return `rgb(${rgb[0]}, ${rgb[1]}, ${rgb[2]})`;
+}
+
+// Controls of each mode, in the same order as light-tui.
+const CONTROLS = {
+  cct: [
+    { key: "dim", label: "Dim", min: 0, max: 100, unit: "%" },
+    { key: "ct", label: "CT", min: 2700, max: 7500, unit: "K", step: 50 },
+    { key: "gm", label: "G/M", min: -100, max: 100, unit: "" },
+  ],
+  hsi: [
+    { key: "hue", label: "Hue", min: 0, max: 360, unit: "°" },
+    { key: "sat", label: "Sat", min: 0, max: 100, unit: "%" },
+    { key: "dim", label: "Int", min: 0, max: 100, unit: "%" },
+  ],
+};
+
+const app = {
+  // Light descriptions and states, as sent by the server.
+  info: [],
+  states: [],
+  selected: new Set(),
+  // Light that was tapped last, whose values are shown in the controls.
+  current: 0,
+  mode: "cct",
+  values: { dim: 50, ct: 5600, gm: 0, hue: 0, sat: 100 },
+};
+
+const $ = (id) => document.getElementById(id);
+
+function showError(message) {
+  $("error").textContent = message;
+}
+
+/** Lights that the controls change: the selected lights, or the current light if none are selected. */
+function targets() {
+  if (app.selected.size > 0) {
+    return [...app.selected].sort((a, b) => a - b);
+  }
+  return app.info.length > 0 ? [app.current] : [];
+}
+
+/** Color temperature range that is valid for all target lights. */
+function ctRange() {
+  const ranges = targets().map((idx) => app.info[idx].ct_range);
+  const min = Math.max(...ranges.map((r) => r[0]));
+  const max = Math.min(...ranges.map((r) => r[1]));
+  return ranges.length > 0 && min < max ? [min, max] : [2700, 7500];
+}
+
+// Changes to send, per light. Only one request per light is in flight, so
+// dragging a slider doesn't queue up requests.
+const pending = new Map();
+const inFlight = new Set();
+
+async function flush(idx) {
+  const body = pending.get(idx);
+  pending.delete(idx);
+  inFlight.add(idx);
+  try {
+    const res = await fetch(`lights/${idx}`, {
+      method: "PATCH",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(body),
+    });
+    if (res.ok) {
+      showError("");
+    } else {
+      const err = await res.json();
+      showError(`${app.info[idx].name}: ${err.error}`);
+    }
+  } catch (e) {
+    showError(String(e));
+  }
+  inFlight.delete(idx);
+  if (pending.has(idx)) {
+    flush(idx);
+  }
+}
+
+function send(idx, state) {
+  pending.set(idx, state);
+  if (!inFlight.has(idx)) {
+    flush(idx);
+  }
+}
+
+/** Set the target lights to the values of the controls. */
+function updateTargets() {
+  const [ctMin, ctMax] = ctRange();
+  app.values.ct = Math.min(ctMax, Math.max(ctMin, app.values.ct));
+  const state = { mode: app.mode };
+  for (const control of CONTROLS[app.mode]) {
+    state[control.key] = app.values[control.key];
+  }
+  // Leave out lights that don't support the mode, so that the others still change.
+  const supported = targets().filter((idx) => app.info[idx].modes.includes(app.mode));
+  for (const idx of supported) {
+    app.states[idx] = { ...app.states[idx], ...state };
+    send(idx, state);
+  }
+  renderLights();
+}
+
+/** Show the values of the current light in the controls. */
+function syncControls() {
+  const state = app.states[app.current];
+  if (!state) {
+    return;
+  }
+  if (state.mode) {
+    app.mode = state.mode;
+  }
+  for (const key of Object.keys(app.values)) {
+    if (state[key] !== null && state[key] !== undefined) {
+      app.values[key] = state[key];
+    }
+  }
+  renderControls();
+}
+
+function renderLights() {
+  const list = $("lights");
+  list.replaceChildren();
+  app.info.forEach((info, idx) => {
+    const item = document.createElement("li");
+    item.classList.toggle("current", idx === app.current);
+
+    const checkbox = document.createElement("input");
+    checkbox.type = "checkbox";
+    checkbox.checked = app.selected.has(idx);
+    checkbox.addEventListener("click", (e) => {
+      e.stopPropagation();
+      if (checkbox.checked) {
+        app.selected.add(idx);
+      } else {
+        app.selected.delete(idx);
+      }
+      renderControls();
+    });
+
+    const preview = document.createElement("span");
+    preview.className = "preview";
+    preview.style.background = computePreview(app.states[idx] ?? {});
+
+    const name = document.createElement("span");
+    name.textContent = info.name;
+
+    item.append(checkbox, preview, name);
+    item.addEventListener("click", () => {
+      app.current = idx;
+      syncControls();
+      renderLights();
+    });
+    list.append(item);
+  });
+}
+
+function renderControls() {
+  for (const button of $("modes").querySelectorAll("button")) {
+    button.classList.toggle("active", button.dataset.mode === app.mode);
+  }
+  const [ctMin, ctMax] = ctRange();
+  const controls = $("controls");
+  controls.replaceChildren();
+  for (const control of CONTROLS[app.mode]) {
+    const [min, max] = control.key === "ct" ? [ctMin, ctMax] : [control.min, control.max];
+    const label = document.createElement("label");
+    const value = document.createElement("span");
+    value.className = "value";
+    value.textContent = `${app.values[control.key]}${control.unit}`;
+    const slider = document.createElement("input");
+    slider.type = "range";
+    slider.min = min;
+    slider.max = max;
+    slider.step = control.step ?? 1;
+    slider.value = app.values[control.key];
+    slider.addEventListener("input", () => {
+      app.values[control.key] = Number(slider.value);
+      value.textContent = `${slider.value}${control.unit}`;
+      updateTargets();
+    });
+    label.append(`${control.label} `, value, slider);
+    controls.append(label);
+  }
+}
+
+async function loadScenes() {
+  const res = await fetch("scenes");
+  const scenes = await res.json();
+  const container = $("scenes");
+  container.replaceChildren();
+  for (const name of scenes) {
+    const button = document.createElement("button");
+    button.textContent = name;
+    button.addEventListener("click", async () => {
+      const res = await fetch(`scenes/${encodeURIComponent(name)}/recall`, { method: "POST" });
+      showError(res.ok ? "" : (await res.json()).error);
+    });
+    container.append(button);
+  }
+}
+
+function connect() {
+  const url = new URL("ws", location.href);
+  url.protocol = url.protocol === "https:" ? "wss:" : "ws:";
+  const ws = new WebSocket(url);
+  let first = true;
+  ws.addEventListener("open", () => {
+    $("status").textContent = "Connected";
+    $("status").classList.add("connected");
+    loadScenes().catch((e) => showError(String(e)));
+  });
+  ws.addEventListener("message", (event) => {
+    const msg = JSON.parse(event.data);
+    switch (msg.response) {
+      case "describe":
+        app.info = msg.lights;
+        app.selected = new Set([...app.selected].filter((idx) => idx < app.info.length));
+        if (app.current >= app.info.length) {
+          app.current = 0;
+        }
+        break;
+      case "state":
+        app.states = msg.state;
+        if (first) {
+          first = false;
+          syncControls();
+        }
+        break;
+      case "update":
+        app.states[msg.idx] = msg.light;
+        break;
+    }
+    renderLights();
+  });
+  ws.addEventListener("close", () => {
+    $("status").textContent = "Disconnected";
+    $("status").classList.remove("connected");
+    setTimeout(connect, 2000);
+  });
+}
+
+for (const button of $("modes").querySelectorAll("button")) {
+  button.addEventListener("click", () => {
+    app.mode = button.dataset.mode;
+    renderControls();
+    updateTargets();
+  });
+}
+$("select-all").addEventListener("click", () => {
+  app.selected = new Set(app.info.map((_, idx) => idx));
+  renderLights();
+  renderControls();
+});
+$("select-none").addEventListener("click", () => {
+  app.selected.clear();
+  renderLights();
+  renderControls();
+});
+
+renderControls();
+connect();
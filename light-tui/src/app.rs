@@ -1,5 +1,7 @@
 use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
-use light_protocol::{ModeType, State};
+use light_protocol::{
+    Command, CommandType, Hello, LightInfo, ModeType, PROTOCOL_VERSION, Playback, State,
+};
 use ratatui::layout::{Position, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use std::collections::HashSet;
@@ -11,12 +13,14 @@ pub struct Theme {
     pub normal_item: Style,
     pub focus_item: Style,
     pub title_style: Style,
+    pub error_style: Style,
     pub gauge_style: Style,
 }
 
 pub struct App {
     pub first_connect: bool,
     pub lights: Vec<State>,
+    pub light_info: Vec<LightInfo>,
     pub selected_indices: HashSet<usize>,
     pub current_mode: ModeType,
     pub input_mode: InputMode,
@@ -35,10 +39,33 @@ pub struct App {
     pub sat: u8,
 
     pub list_cursor: usize,
+
+    // Scenes
+    pub scenes: Vec<String>,
+    pub scene_cursor: usize,
+    pub scene_name_input: String,
+
+    // Cue lists
+    pub cuelists: Vec<String>,
+    pub playback: Playback,
+    pub cue_cursor: usize,
+
+    /// Commands to send to the server, other than light changes.
+    pub pending_commands: Vec<Command>,
+
+    /// Last error from the server, shown until the next key press.
+    pub error: Option<String>,
+
+    /// Server description, if the server sent one.
+    pub hello: Option<Hello>,
+    /// Whether the server speaks an incompatible protocol version.
+    pub incompatible: bool,
 }
 
 pub struct MouseAreas {
     pub lights: Vec<(usize, Rect)>,
+    pub scenes: Vec<(usize, Rect)>,
+    pub cues: Vec<(usize, Rect)>,
     pub modes: Vec<(ModeType, Rect)>,
     pub sliders: Vec<(ControlTarget, Rect)>,
 }
@@ -47,11 +74,14 @@ pub struct MouseAreas {
 pub enum InputMode {
     Navigation,
     Editing,
+    SceneName,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Focus {
     LightList,
+    SceneList,
+    CueList,
     Control(ControlTarget),
 }
 
@@ -69,6 +99,8 @@ impl MouseAreas {
     pub fn new() -> MouseAreas {
         MouseAreas {
             lights: Vec::new(),
+            scenes: Vec::new(),
+            cues: Vec::new(),
             modes: Vec::new(),
             sliders: Vec::new(),
         }
@@ -93,6 +125,7 @@ impl App {
         App {
             first_connect: true,
             lights: vec![],
+            light_info: vec![],
             selected_indices: HashSet::new(),
             list_cursor: 0,
             current_mode: ModeType::CCT,
@@ -109,6 +142,7 @@ impl App {
                 title_style: Style::default()
                     .fg(Color::Cyan)
                     .add_modifier(Modifier::BOLD),
+                error_style: Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
                 gauge_style: Style::default().fg(Color::Cyan),
             },
 
@@ -118,32 +152,182 @@ impl App {
 
             hue: 0,
             sat: 0,
+
+            scenes: vec![],
+            scene_cursor: 0,
+            scene_name_input: String::new(),
+
+            cuelists: vec![],
+            playback: Playback::default(),
+            cue_cursor: 0,
+
+            pending_commands: vec![],
+
+            error: None,
+
+            hello: None,
+            incompatible: false,
+        }
+    }
+
+    /** Name of light, as described by the server. */
+    pub fn light_name(&self, idx: usize) -> String {
+        match self.light_info.get(idx) {
+            Some(info) => info.name.clone(),
+            None => format!("Light #{}", idx + 1),
+        }
+    }
+
+    /** Store the server description, and check that it speaks our protocol. */
+    pub fn set_hello(&mut self, hello: Hello) {
+        self.incompatible = hello.protocol != PROTOCOL_VERSION;
+        if self.incompatible {
+            self.error = Some(format!(
+                "{} uses protocol version {}, expected {}",
+                hello.server, hello.protocol, PROTOCOL_VERSION
+            ));
+        }
+        // Stay in a mode that the lights support.
+        if !hello.modes.is_empty() && !hello.modes.contains(&self.current_mode) {
+            self.current_mode = hello.modes[0];
+        }
+        self.hello = Some(hello);
+    }
+
+    /** Whether the server has a capability. Servers from before the hello message
+     * have none, they only understand setting single lights.
+     */
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.hello
+            .as_ref()
+            .is_some_and(|hello| hello.has_capability(capability))
+    }
+
+    /** Whether any light supports a mode. */
+    pub fn supports_mode(&self, mode: ModeType) -> bool {
+        self.hello
+            .as_ref()
+            .is_none_or(|hello| hello.modes.contains(&mode))
+    }
+
+    /** Range of a control, taking into account the color temperature range of the
+     * selected lights (or the light under the cursor if none are selected).
+     */
+    pub fn control_range(&self, target: ControlTarget) -> (i32, i32) {
+        if target != ControlTarget::CT {
+            let Some(hello) = &self.hello else {
+                return target.range();
+            };
+            let wide = |(min, max): (u16, u16)| (min as i32, max as i32);
+            return match target {
+                ControlTarget::Dim | ControlTarget::Int => wide(hello.ranges.dim),
+                ControlTarget::GM => (hello.ranges.gm.0 as i32, hello.ranges.gm.1 as i32),
+                ControlTarget::Hue => wide(hello.ranges.hue),
+                ControlTarget::Sat => wide(hello.ranges.sat),
+                ControlTarget::CT => unreachable!(),
+            };
+        }
+        let ct_range = |idx: &usize| self.light_info.get(*idx).map(|info| info.ct_range);
+        let mut ranges: Vec<(u16, u16)> =
+            self.selected_indices.iter().filter_map(ct_range).collect();
+        if ranges.is_empty() {
+            ranges.extend(ct_range(&self.list_cursor));
+        }
+        // Use the range that is valid for all lights.
+        let min = ranges.iter().map(|r| r.0).max();
+        let max = ranges.iter().map(|r| r.1).min();
+        match (min, max) {
+            (Some(min), Some(max)) if min < max => (min as i32, max as i32),
+            _ => target.range(),
+        }
+    }
+
+    /** Show an error from the server, with the command that caused it if known. */
+    pub fn set_error(&mut self, command: Option<&Command>, message: &str) {
+        let cmd_name = command
+            .and_then(|command| serde_json::to_value(command.cmd).ok())
+            .and_then(|value| value.as_str().map(str::to_string));
+        self.error = Some(match cmd_name {
+            Some(cmd_name) => format!("{}: {}", cmd_name, message),
+            None => message.to_string(),
+        });
+    }
+
+    /** Update the list of scenes, as received from the server. */
+    pub fn set_scenes(&mut self, scenes: Vec<String>) {
+        self.scenes = scenes;
+        if self.scene_cursor >= self.scenes.len() {
+            self.scene_cursor = self.scenes.len().saturating_sub(1);
+        }
+        if self.focus == Focus::SceneList && self.scenes.is_empty() {
+            self.focus = Focus::LightList;
+        }
+    }
+
+    /** Update the cue playback status, as received from the server. */
+    pub fn set_playback(&mut self, playback: Playback) {
+        // Follow the current cue if the cue list changed or playback moved on.
+        if playback.cuelist != self.playback.cuelist || playback.cue != self.playback.cue {
+            self.cue_cursor = playback.cue.unwrap_or(0);
+        }
+        self.playback = playback;
+        if self.cue_cursor >= self.playback.cues.len() {
+            self.cue_cursor = self.playback.cues.len().saturating_sub(1);
+        }
+        if self.focus == Focus::CueList && self.playback.cues.is_empty() {
+            self.focus = Focus::LightList;
         }
     }
 
     pub fn handle_key_event(&mut self, key: KeyEvent) {
-        if self.input_mode == InputMode::Navigation {
-            match key.code {
-                // 'q' and CTRL-'c' are handled in src/main.rs
+        match self.input_mode {
+            InputMode::Navigation => match key.code {
+                // 'q' and CTRL-'c' are handled in src/main.rs, except for 'q' in the scene name prompt
                 KeyCode::Down | KeyCode::Char('j') => self.move_focus(1),
                 KeyCode::Up | KeyCode::Char('k') => self.move_focus(-1),
                 KeyCode::Right | KeyCode::Char('l') => self.switch_focus(true),
                 KeyCode::Left | KeyCode::Char('h') => self.switch_focus(false),
                 KeyCode::Char(' ') => self.toggle_selection(),
+                KeyCode::Enter if self.focus == Focus::SceneList => self.recall_scene(),
+                KeyCode::Enter if self.focus == Focus::CueList => self.jump_cue(),
                 KeyCode::Enter => self.toggle_edit_mode(),
                 KeyCode::Tab => self.toggle_mode(),
+                KeyCode::Char('s') if self.has_capability("scenes") => {
+                    self.scene_name_input.clear();
+                    self.input_mode = InputMode::SceneName;
+                }
+                KeyCode::Char('d') | KeyCode::Delete if self.focus == Focus::SceneList => {
+                    self.delete_scene()
+                }
+                KeyCode::Char('c' | 'g' | 'b' | 'p') if !self.has_capability("cuelists") => {}
+                KeyCode::Char('c') => self.load_next_cuelist(),
+                KeyCode::Char('g') => self.pending_commands.push(Command::new(CommandType::CueGo)),
+                KeyCode::Char('b') => self
+                    .pending_commands
+                    .push(Command::new(CommandType::CueBack)),
+                KeyCode::Char('p') => self.toggle_pause(),
                 _ => {}
-            }
-        } else {
-            // Editing mode
-            match key.code {
+            },
+            InputMode::Editing => match key.code {
                 KeyCode::Esc | KeyCode::Enter => self.input_mode = InputMode::Navigation,
                 KeyCode::Right | KeyCode::Char('l') => self.adjust_value(1),
                 KeyCode::Left | KeyCode::Char('h') => self.adjust_value(-1),
                 KeyCode::Up | KeyCode::Char('k') => self.adjust_value(10),
                 KeyCode::Down | KeyCode::Char('j') => self.adjust_value(-10),
                 _ => {}
-            }
+            },
+            InputMode::SceneName => match key.code {
+                KeyCode::Esc => self.input_mode = InputMode::Navigation,
+                KeyCode::Enter => {
+                    self.save_scene();
+                    self.input_mode = InputMode::Navigation;
+                }
+                KeyCode::Backspace => {
+                    self.scene_name_input.pop();
+                }
+                KeyCode::Char(c) => self.scene_name_input.push(c),
+                _ => {}
+            },
         }
     }
 
@@ -157,8 +341,22 @@ impl App {
                     self.toggle_selection();
                 }
             }
-            for (mode, area) in &mouse_areas.modes {
+            for (index, area) in &mouse_areas.scenes {
                 if area.contains(pos) {
+                    self.scene_cursor = *index;
+                    self.focus = Focus::SceneList;
+                    self.recall_scene();
+                }
+            }
+            for (index, area) in &mouse_areas.cues {
+                if area.contains(pos) {
+                    self.cue_cursor = *index;
+                    self.focus = Focus::CueList;
+                    self.jump_cue();
+                }
+            }
+            for (mode, area) in &mouse_areas.modes {
+                if area.contains(pos) && self.supports_mode(*mode) {
                     self.current_mode = *mode;
                 }
             }
@@ -169,7 +367,7 @@ impl App {
             for (target, area) in &mouse_areas.sliders {
                 if area.contains(pos) {
                     self.focus = Focus::Control(*target);
-                    let (min, max) = target.range();
+                    let (min, max) = self.control_range(*target);
                     let val = min
                         + (pos.x as i32 - area.x as i32) * (max - min) / (area.width as i32 - 1);
                     match target {
@@ -203,9 +401,51 @@ impl App {
                     return;
                 }
                 let new_cursor = self.list_cursor as i32 + delta;
+                if new_cursor >= self.lights.len() as i32 {
+                    if !self.scenes.is_empty() {
+                        // Move down into scene list
+                        self.focus = Focus::SceneList;
+                        self.scene_cursor = 0;
+                        return;
+                    }
+                    if !self.playback.cues.is_empty() {
+                        // Move down into cue list
+                        self.focus = Focus::CueList;
+                        self.cue_cursor = 0;
+                        return;
+                    }
+                }
                 self.list_cursor = new_cursor.clamp(0, self.lights.len() as i32 - 1) as usize;
                 self.sync_controls_with_cursor();
             }
+            Focus::SceneList => {
+                let new_cursor = self.scene_cursor as i32 + delta;
+                if new_cursor < 0 {
+                    // Move up into light list
+                    self.focus = Focus::LightList;
+                    return;
+                }
+                if new_cursor >= self.scenes.len() as i32 && !self.playback.cues.is_empty() {
+                    // Move down into cue list
+                    self.focus = Focus::CueList;
+                    self.cue_cursor = 0;
+                    return;
+                }
+                self.scene_cursor = new_cursor.clamp(0, self.scenes.len() as i32 - 1) as usize;
+            }
+            Focus::CueList => {
+                let new_cursor = self.cue_cursor as i32 + delta;
+                if new_cursor < 0 {
+                    // Move up into scene list, or light list if there are no scenes
+                    self.focus = if self.scenes.is_empty() {
+                        Focus::LightList
+                    } else {
+                        Focus::SceneList
+                    };
+                    return;
+                }
+                self.cue_cursor = new_cursor.clamp(0, self.playback.cues.len() as i32 - 1) as usize;
+            }
             Focus::Control(target) => {
                 // Move up/down between controls
                 let order = match self.current_mode {
@@ -224,7 +464,10 @@ impl App {
 
     fn switch_focus(&mut self, right: bool) {
         if right {
-            if self.focus == Focus::LightList {
+            if matches!(
+                self.focus,
+                Focus::LightList | Focus::SceneList | Focus::CueList
+            ) {
                 // Jump directly to controls
                 self.focus = Focus::Control(match self.current_mode {
                     ModeType::CCT => ControlTarget::Dim,
@@ -232,13 +475,81 @@ impl App {
                 });
             }
         } else {
-            match self.focus {
-                Focus::Control(_) => self.focus = Focus::LightList,
-                _ => {}
+            if let Focus::Control(_) = self.focus {
+                self.focus = Focus::LightList;
             }
         }
     }
 
+    fn save_scene(&mut self) {
+        let name = self.scene_name_input.trim();
+        if name.is_empty() {
+            return;
+        }
+        self.pending_commands.push(Command {
+            name: Some(name.to_string()),
+            ..Command::new(CommandType::SceneSave)
+        });
+        self.pending_commands
+            .push(Command::new(CommandType::SceneList));
+    }
+
+    fn recall_scene(&mut self) {
+        if let Some(name) = self.scenes.get(self.scene_cursor) {
+            self.pending_commands.push(Command {
+                name: Some(name.clone()),
+                ..Command::new(CommandType::SceneRecall)
+            });
+        }
+    }
+
+    fn delete_scene(&mut self) {
+        if let Some(name) = self.scenes.get(self.scene_cursor) {
+            self.pending_commands.push(Command {
+                name: Some(name.clone()),
+                ..Command::new(CommandType::SceneDelete)
+            });
+            self.pending_commands
+                .push(Command::new(CommandType::SceneList));
+        }
+    }
+
+    /** Load the cue list after the currently loaded one. */
+    fn load_next_cuelist(&mut self) {
+        if self.cuelists.is_empty() {
+            return;
+        }
+        let next = match &self.playback.cuelist {
+            Some(name) => match self.cuelists.iter().position(|n| n == name) {
+                Some(pos) => (pos + 1) % self.cuelists.len(),
+                None => 0,
+            },
+            None => 0,
+        };
+        self.pending_commands.push(Command {
+            name: Some(self.cuelists[next].clone()),
+            ..Command::new(CommandType::CueLoad)
+        });
+    }
+
+    fn jump_cue(&mut self) {
+        if self.cue_cursor < self.playback.cues.len() {
+            self.pending_commands.push(Command {
+                cue: Some(self.cue_cursor),
+                ..Command::new(CommandType::CueJump)
+            });
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        let cmd = if self.playback.paused {
+            CommandType::CueResume
+        } else {
+            CommandType::CuePause
+        };
+        self.pending_commands.push(Command::new(cmd));
+    }
+
     fn toggle_selection(&mut self) {
         if self.focus == Focus::LightList && !self.lights.is_empty() {
             if self.selected_indices.contains(&self.list_cursor) {
@@ -250,10 +561,14 @@ impl App {
     }
 
     fn toggle_mode(&mut self) {
-        self.current_mode = match self.current_mode {
+        let mode = match self.current_mode {
             ModeType::CCT => ModeType::HSI,
             ModeType::HSI => ModeType::CCT,
         };
+        if !self.supports_mode(mode) {
+            return;
+        }
+        self.current_mode = mode;
         // Reset focus to top of controls if we are currently focusing a control
         if let Focus::Control(_) = self.focus {
             self.focus = Focus::Control(match self.current_mode {
@@ -305,41 +620,38 @@ impl App {
     }
 
     fn adjust_value(&mut self, delta: i32) {
-        match self.focus {
-            Focus::Control(control) => {
-                let (min, max) = control.range();
-                match control {
-                    ControlTarget::Dim => {
-                        self.dim = (self.dim as i32 + delta).clamp(min, max) as u8
-                    }
-                    ControlTarget::CT => {
-                        let step = if delta.abs() >= 10 {
-                            delta * 10
-                        } else {
-                            delta * 50
-                        };
-                        self.ct = (self.ct as i32 + step).clamp(min, max) as u16;
-                    }
-                    ControlTarget::GM => self.gm = (self.gm as i32 + delta).clamp(min, max) as i8,
-                    ControlTarget::Hue => {
-                        self.hue = (self.hue as i32 + delta).clamp(min, max) as u16
-                    }
-                    ControlTarget::Sat => {
-                        self.sat = (self.sat as i32 + delta).clamp(min, max) as u8
-                    }
-                    ControlTarget::Int => {
-                        self.dim = (self.dim as i32 + delta).clamp(min, max) as u8
-                    }
+        if let Focus::Control(control) = self.focus {
+            let (min, max) = self.control_range(control);
+            match control {
+                ControlTarget::Dim => self.dim = (self.dim as i32 + delta).clamp(min, max) as u8,
+                ControlTarget::CT => {
+                    let step = if delta.abs() >= 10 {
+                        delta * 10
+                    } else {
+                        delta * 50
+                    };
+                    self.ct = (self.ct as i32 + step).clamp(min, max) as u16;
                 }
+                ControlTarget::GM => self.gm = (self.gm as i32 + delta).clamp(min, max) as i8,
+                ControlTarget::Hue => self.hue = (self.hue as i32 + delta).clamp(min, max) as u16,
+                ControlTarget::Sat => self.sat = (self.sat as i32 + delta).clamp(min, max) as u8,
+                ControlTarget::Int => self.dim = (self.dim as i32 + delta).clamp(min, max) as u8,
             }
-            _ => {}
         }
         self.update_selected_lights();
     }
 
     fn update_selected_lights(&mut self) {
+        // Selection might have changed to lights with a smaller color temperature range.
+        let (ct_min, ct_max) = self.control_range(ControlTarget::CT);
+        self.ct = (self.ct as i32).clamp(ct_min, ct_max) as u16;
         for idx in &self.selected_indices {
-            if *idx < self.lights.len() {
+            // Leave out lights that don't support the mode, so that the others still change.
+            let supported = self
+                .light_info
+                .get(*idx)
+                .is_none_or(|info| info.modes.contains(&self.current_mode));
+            if *idx < self.lights.len() && supported {
                 let light = &mut self.lights[*idx];
                 light.mode = Some(self.current_mode);
                 match self.current_mode {
@@ -84,6 +84,7 @@ pub fn hsi_to_rgb(h: u16, s: u16, i: u16) -> (u8, u8, u8) {
     apply_dimming((r as u8, g as u8, b as u8), i)
 }
 
+#[allow(clippy::excessive_precision)]
 pub fn kelvin_to_rgb(k: u16) -> (u8, u8, u8) {
     // Tanner Helland's algorithm approximation
     let temp = (k as f32).clamp(1000.0, 40000.0) / 100.0;
@@ -1,11 +1,13 @@
-use crate::app::{App, MouseAreas};
+use crate::app::{App, InputMode, MouseAreas};
 use anyhow::Result;
 use clap::Parser;
 use crossterm::event::{self as crossterm_event, Event, EventStream, KeyCode, KeyModifiers};
 use futures::StreamExt;
-use light_protocol::{Command, Response, State};
+use light_protocol::{
+    Command, CommandType, Hello, LightChange, PROTOCOL_VERSION, Response, ResponseType,
+};
 use ratatui::Terminal;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -33,8 +35,8 @@ async fn main() -> Result<()> {
 
     // Channel for sending commands to network task
     let (tx_cmd, mut rx_cmd) = mpsc::channel::<Command>(32);
-    // Channel for receiving updates from network task
-    let (tx_update, mut rx_update) = mpsc::channel::<Vec<State>>(32);
+    // Channel for receiving updates from network task, with the command they answer
+    let (tx_update, mut rx_update) = mpsc::channel::<(Option<Command>, Response)>(32);
 
     let network_handle = tokio::spawn(async move {
         loop {
@@ -50,6 +52,10 @@ async fn main() -> Result<()> {
             let (reader, mut writer) = stream.split();
             let mut buf_reader = BufReader::new(reader);
             let mut line = String::new();
+            // Commands sent that haven't been answered yet, by id.
+            let mut next_id: u64 = 0;
+            let mut outstanding: HashMap<u64, Command> = HashMap::new();
+            let mut greeted = false;
 
             loop {
                 tokio::select! {
@@ -60,9 +66,19 @@ async fn main() -> Result<()> {
                             Ok(_) => {
                                 // Parse line
                                 if let Ok(response) = serde_json::from_str::<Response>(&line) {
-                                    if let Some(states) = response.state {
-                                        let _ = tx_update.send(states).await;
+                                    if !greeted {
+                                        greeted = true;
+                                        // Request list of scenes and cue lists, if the server has them.
+                                        for cmd in initial_commands(response.hello.as_ref()) {
+                                            send_command(&mut writer, &mut next_id, &mut outstanding, Command::new(cmd)).await;
+                                        }
                                     }
+                                    let command = response
+                                        .id
+                                        .as_ref()
+                                        .and_then(|id| id.as_u64())
+                                        .and_then(|id| outstanding.remove(&id));
+                                    let _ = tx_update.send((command, response)).await;
                                 }
                                 line.clear();
                             }
@@ -71,10 +87,7 @@ async fn main() -> Result<()> {
                     }
                     // Write to socket
                     Some(cmd) = rx_cmd.recv() => {
-                        if let Ok(json) = serde_json::to_string(&cmd) {
-                            let _ = writer.write_all(json.as_bytes()).await;
-                            let _ = writer.write_all(b"\n").await;
-                        }
+                        send_command(&mut writer, &mut next_id, &mut outstanding, cmd).await;
                     }
                 }
             }
@@ -95,11 +108,48 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/** Commands to send after connecting, depending on the server's hello message.
+ * Servers from before the hello message only understand setting single lights.
+ */
+fn initial_commands(hello: Option<&Hello>) -> Vec<CommandType> {
+    match hello {
+        Some(hello) if hello.protocol != PROTOCOL_VERSION => vec![],
+        Some(hello) => [
+            ("scenes", CommandType::SceneList),
+            ("cuelists", CommandType::CueListList),
+        ]
+        .into_iter()
+        .filter(|(capability, _)| hello.has_capability(capability))
+        .map(|(_, cmd)| cmd)
+        .collect(),
+        None => vec![],
+    }
+}
+
+/** Send a command to the server, with a new id to match the response with. */
+async fn send_command(
+    writer: &mut (impl AsyncWriteExt + Unpin),
+    next_id: &mut u64,
+    outstanding: &mut HashMap<u64, Command>,
+    cmd: Command,
+) {
+    *next_id += 1;
+    let cmd = Command {
+        id: Some((*next_id).into()),
+        ..cmd
+    };
+    if let Ok(json) = serde_json::to_string(&cmd) {
+        let _ = writer.write_all(json.as_bytes()).await;
+        let _ = writer.write_all(b"\n").await;
+        outstanding.insert(*next_id, cmd);
+    }
+}
+
 async fn run_app<B: ratatui::backend::Backend>(
     terminal: &mut Terminal<B>,
     app: &mut App,
     tx_cmd: mpsc::Sender<Command>,
-    rx_update: &mut mpsc::Receiver<Vec<State>>,
+    rx_update: &mut mpsc::Receiver<(Option<Command>, Response)>,
 ) -> Result<()>
 where
     <B as ratatui::backend::Backend>::Error: Send + Sync + 'static,
@@ -113,30 +163,77 @@ where
         })?;
 
         tokio::select! {
-            Some(states) = rx_update.recv() => {
-                app.lights = states;
-                if app.first_connect {
-                    app.first_connect = false;
-                    // Select all lights on first succesful connect.
-                    app.selected_indices = HashSet::from_iter(0..app.lights.len());
-                    app.sync_controls_with_cursor();
+            Some((command, response)) = rx_update.recv() => {
+                match response.response {
+                    ResponseType::Hello => {
+                        if let Some(hello) = response.hello {
+                            app.set_hello(hello);
+                        }
+                    }
+                    ResponseType::Err => {
+                        app.set_error(command.as_ref(), response.error.as_deref().unwrap_or("error"));
+                    }
+                    ResponseType::State => {
+                        if let Some(states) = response.state {
+                            app.lights = states;
+                            if app.first_connect {
+                                app.first_connect = false;
+                                // Select all lights on first succesful connect.
+                                app.selected_indices = HashSet::from_iter(0..app.lights.len());
+                                app.sync_controls_with_cursor();
+                            }
+                        }
+                    }
+                    ResponseType::Scenes => {
+                        if let Some(scenes) = response.scenes {
+                            app.set_scenes(scenes);
+                        }
+                    }
+                    ResponseType::CueLists => {
+                        if let Some(cuelists) = response.cuelists {
+                            app.cuelists = cuelists;
+                        }
+                    }
+                    ResponseType::Playback => {
+                        if let Some(playback) = response.playback {
+                            app.set_playback(playback);
+                        }
+                    }
+                    ResponseType::Describe => {
+                        if let Some(lights) = response.lights {
+                            app.light_info = lights;
+                        }
+                    }
+                    ResponseType::Update => {
+                        // Light changed by another client.
+                        if let (Some(idx), Some(light)) = (response.idx, response.light) {
+                            let idx = idx as usize;
+                            if idx < app.lights.len() {
+                                app.lights[idx] = light;
+                                if idx == app.list_cursor {
+                                    app.sync_controls_with_cursor();
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
                 }
             }
             Some(Ok(event)) = event_stream.next() => {
                 let old_states = app.lights.clone();
+                if matches!(event, Event::Key(_)) && !app.incompatible {
+                    app.error = None;
+                }
                 match event {
-                    Event::Key(key) => {
-                        if key.kind == crossterm_event::KeyEventKind::Press {
-                            match key.code {
-                                KeyCode::Char('q') => return Ok(()),
-                                KeyCode::Char('c') => {
-                                    if key.modifiers.contains(KeyModifiers::CONTROL) {
-                                        return Ok(());
-                                    }
-                                },
-                                _ => {
-                                    app.handle_key_event(key);
-                                }
+                    Event::Key(key) if key.kind == crossterm_event::KeyEventKind::Press => {
+                        match key.code {
+                            // Scene names can contain any character.
+                            KeyCode::Char('q') if app.input_mode != InputMode::SceneName => return Ok(()),
+                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                return Ok(());
+                            },
+                            _ => {
+                                app.handle_key_event(key);
                             }
                         }
                     },
@@ -145,16 +242,41 @@ where
                     },
                     _ => {}
                 }
+                if app.incompatible {
+                    // Don't send commands the server might misunderstand.
+                    app.pending_commands.clear();
+                    continue;
+                }
+                let mut changes: Vec<LightChange> = vec![];
                 for idx in &app.selected_indices {
                     if *idx < app.lights.len() && *idx < old_states.len() && app.lights[*idx] != old_states[*idx] {
-                        let new_state = &app.lights[*idx];
-                        let cmd = Command {
+                        changes.push(LightChange {
                             idx: *idx as u16,
-                            state: new_state.clone(),
+                            state: app.lights[*idx].clone(),
+                        });
+                    }
+                }
+                if changes.len() > 1 && app.has_capability("batch") {
+                    // Change all lights at once, instead of one after another.
+                    changes.sort_by_key(|change| change.idx);
+                    let cmd = Command {
+                        lights: Some(changes),
+                        ..Command::new(CommandType::Batch)
+                    };
+                    let _ = tx_cmd.send(cmd).await;
+                } else {
+                    for change in changes {
+                        let cmd = Command {
+                            idx: Some(change.idx),
+                            state: change.state,
+                            ..Command::new(CommandType::Set)
                         };
                         let _ = tx_cmd.send(cmd).await;
                     }
                 }
+                for cmd in app.pending_commands.drain(..) {
+                    let _ = tx_cmd.send(cmd).await;
+                }
             }
         }
     }
@@ -17,16 +17,20 @@ pub fn draw(f: &mut Frame, app: &App) -> MouseAreas {
         .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
         .split(f.area());
 
-    // Header
-    let title =
-        Paragraph::new("Light Control - 'q' to quit, '↑↓←→' to navigate, 'Tab' mode, 'Enter' edit")
-            .style(app.theme.title_style)
-            .alignment(Alignment::Center)
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .border_type(BorderType::Rounded),
-            );
+    // Header, or the last error
+    let title = match &app.error {
+        Some(error) => Paragraph::new(format!("Error: {}", error)).style(app.theme.error_style),
+        None => Paragraph::new(
+            "Light Control - 'q' to quit, '↑↓←→' to navigate, 'Tab' mode, 'Enter' edit, 's' save scene, 'c' cue list, 'g' go",
+        )
+        .style(app.theme.title_style),
+    }
+    .alignment(Alignment::Center)
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded),
+    );
     f.render_widget(title, chunks[0]);
 
     // Main area layout
@@ -35,8 +39,21 @@ pub fn draw(f: &mut Frame, app: &App) -> MouseAreas {
         .constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref())
         .split(chunks[1]);
 
-    // Left: Light List
-    draw_light_list(f, app, main_chunks[0], &mut mouse_areas);
+    // Left: Light List, Scene List and Cue List, if the server supports them
+    let panel = |capability| {
+        if app.has_capability(capability) {
+            Constraint::Percentage(30)
+        } else {
+            Constraint::Length(0)
+        }
+    };
+    let left_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), panel("scenes"), panel("cuelists")].as_ref())
+        .split(main_chunks[0]);
+    draw_light_list(f, app, left_chunks[0], &mut mouse_areas);
+    draw_scene_list(f, app, left_chunks[1], &mut mouse_areas);
+    draw_cue_list(f, app, left_chunks[2], &mut mouse_areas);
 
     // Right: Controls
     draw_controls(f, app, main_chunks[1], &mut mouse_areas);
@@ -54,7 +71,7 @@ fn draw_light_list(f: &mut Frame, app: &App, area: Rect, mouse_areas: &mut Mouse
 
         // Create a span for the checkbox and text
         let checkbox = Span::raw(checkbox);
-        let name = Span::raw(format!(" Light #{}", i + 1));
+        let name = Span::raw(format!(" {}", app.light_name(i)));
 
         // Create a span for the color preview
         // We use a block character and set its fg/bg
@@ -86,6 +103,79 @@ fn draw_light_list(f: &mut Frame, app: &App, area: Rect, mouse_areas: &mut Mouse
     f.render_widget(list, area);
 }
 
+fn draw_scene_list(f: &mut Frame, app: &App, area: Rect, mouse_areas: &mut MouseAreas) {
+    let mut items: Vec<ListItem> = Vec::new();
+    for (i, name) in app.scenes.iter().enumerate() {
+        let style = if app.focus == Focus::SceneList && app.scene_cursor == i {
+            app.theme.focus_item
+        } else {
+            app.theme.normal_item
+        };
+        items.push(ListItem::new(name.as_str()).style(style));
+        mouse_areas
+            .scenes
+            .push((i, Rect::new(area.x, area.y + 1 + i as u16, area.width, 1)));
+    }
+
+    let title = if app.input_mode == InputMode::SceneName {
+        format!("Save scene: {}_", app.scene_name_input)
+    } else {
+        "Scenes".to_string()
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_style(if app.input_mode == InputMode::SceneName {
+            app.theme.edit_control
+        } else if app.focus == Focus::SceneList {
+            app.theme.focus_control
+        } else {
+            app.theme.normal_control
+        });
+
+    let list = List::new(items).block(block);
+    f.render_widget(list, area);
+}
+
+fn draw_cue_list(f: &mut Frame, app: &App, area: Rect, mouse_areas: &mut MouseAreas) {
+    let mut items: Vec<ListItem> = Vec::new();
+    for (i, cue) in app.playback.cues.iter().enumerate() {
+        // Mark the current cue.
+        let marker = if app.playback.cue == Some(i) {
+            "> "
+        } else {
+            "  "
+        };
+        let label = cue.label.as_deref().unwrap_or(&cue.scene);
+        let style = if app.focus == Focus::CueList && app.cue_cursor == i {
+            app.theme.focus_item
+        } else {
+            app.theme.normal_item
+        };
+        items.push(ListItem::new(format!("{}{} {}", marker, i + 1, label)).style(style));
+        mouse_areas
+            .cues
+            .push((i, Rect::new(area.x, area.y + 1 + i as u16, area.width, 1)));
+    }
+
+    let title = match &app.playback.cuelist {
+        Some(name) if app.playback.paused => format!("Cues: {} (paused)", name),
+        Some(name) => format!("Cues: {}", name),
+        None => "Cues".to_string(),
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_style(if app.focus == Focus::CueList {
+            app.theme.focus_control
+        } else {
+            app.theme.normal_control
+        });
+
+    let list = List::new(items).block(block);
+    f.render_widget(list, area);
+}
+
 fn draw_controls(f: &mut Frame, app: &App, area: Rect, mouse_areas: &mut MouseAreas) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -148,14 +238,15 @@ fn draw_cct_controls(f: &mut Frame, app: &App, area: Rect, mouse_areas: &mut Mou
         ControlTarget::Dim,
         mouse_areas,
     );
+    let (ct_min, ct_max) = app.control_range(ControlTarget::CT);
     draw_slider(
         f,
         app,
         chunks[1],
-        "Color Temp (2700-7500K)",
+        &format!("Color Temp ({}-{}K)", ct_min, ct_max),
         app.ct as i16,
-        2700,
-        7500,
+        ct_min as i16,
+        ct_max as i16,
         ControlTarget::CT,
         mouse_areas,
     );
@@ -220,6 +311,7 @@ fn draw_hsi_controls(f: &mut Frame, app: &App, area: Rect, mouse_areas: &mut Mou
     );
 }
 
+#[allow(clippy::too_many_arguments)]
 fn draw_slider(
     f: &mut Frame,
     app: &App,
@@ -15,6 +15,17 @@ lights = [
 def handle_client(conn, addr):
     print(f"Connected by {addr}")
     
+    # Introduce server
+    hello = {
+        "server": "mock_server",
+        "protocol": 1,
+        "capabilities": ["scenes", "cuelists", "ids"],
+        "num_lights": len(lights),
+        "modes": ["cct", "hsi"],
+        "ranges": {"dim": [0, 100], "gm": [-100, 100], "hue": [0, 360], "sat": [0, 100]},
+    }
+    conn.sendall((json.dumps({"response": "hello", "hello": hello}) + "\n").encode('utf-8'))
+
     # Send initial state
     response = {"response": "state", "state": lights}
     conn.sendall((json.dumps(response) + "\n").encode('utf-8'))
@@ -30,7 +41,19 @@ def handle_client(conn, addr):
             try:
                 cmd = json.loads(line)
                 print(f"Received: {cmd}")
-                
+                # Responses to a command carry its id, if any.
+                reply = {"id": cmd["id"]} if "id" in cmd else {}
+
+                if cmd.get('cmd', 'set') == 'scene_list':
+                    conn.sendall((json.dumps({**reply, "response": "scenes", "scenes": []}) + "\n").encode('utf-8'))
+                    continue
+                elif cmd.get('cmd', 'set') == 'cuelist_list':
+                    conn.sendall((json.dumps({**reply, "response": "cuelists", "cuelists": []}) + "\n").encode('utf-8'))
+                    continue
+                elif cmd.get('cmd', 'set') != 'set':
+                    conn.sendall((json.dumps({**reply, "response": "ok"}) + "\n").encode('utf-8'))
+                    continue
+
                 idx = cmd['idx']
                 if idx < len(lights):
                     # Update state
@@ -43,7 +66,7 @@ def handle_client(conn, addr):
                 # but real server broadcasts or client polling handles it)
                 # The real server sends OK, then potentially state updates if logic changes it.
                 # Here we just send OK.
-                conn.sendall((json.dumps({"response": "ok"}) + "\n").encode('utf-8'))
+                conn.sendall((json.dumps({**reply, "response": "ok"}) + "\n").encode('utf-8'))
                 
                 # Send updated state back to confirm
                 conn.sendall((json.dumps({"response": "state", "state": lights}) + "\n").encode('utf-8'))